@@ -1,5 +1,4 @@
 use cgfs::*;
-use png;
 use std::fs::File;
 use std::io::BufWriter;
 use std::path::Path;
@@ -7,7 +6,7 @@ use std::path::Path;
 fn save_canvas_to(canvas: &Canvas, p: &str) {
     let path = Path::new(p);
     let file = File::create(path).unwrap();
-    let ref mut w = BufWriter::new(file);
+    let w = &mut BufWriter::new(file);
 
     let mut encoder = png::Encoder::new(w, canvas.get_width(), canvas.get_height());
     encoder.set_color(png::ColorType::RGB);
@@ -60,11 +59,8 @@ fn ray_tracing() {
         },
     };
 
-    let yellow_sphere = SphereObject {
-        sphere: Sphere {
-            center: (0., -5001., 0.).into(),
-            radius: 5000.,
-        },
+    let yellow_floor = PlaneObject {
+        plane: Plane::from_point_normal((0., -1., 0.).into(), (0., 1., 0.).into()),
         material: Material {
             color: Color::yellow(),
             specular: 1000,
@@ -76,7 +72,7 @@ fn ray_tracing() {
     scene.add_object(Box::new(red_sphere));
     scene.add_object(Box::new(blue_sphere));
     scene.add_object(Box::new(green_sphere));
-    scene.add_object(Box::new(yellow_sphere));
+    scene.add_object(Box::new(yellow_floor));
 
     let subtraction = BooleanOperationSpheresObject {
         sphere_a: Sphere {
@@ -157,7 +153,7 @@ fn ray_tracing() {
     }));
     scene.add_light(Box::new(DirectionalLight {
         intensity: 0.2,
-        direction: (1., 4., 4., 0.).into(),
+        direction: (1., 4., 4.).into(),
     }));
 
     scene.set_camera(
@@ -177,20 +173,20 @@ fn ray_tracing() {
         (-3., 5., 5.).into(),
     ];
 
-    let polyhedron = PolyhedronObject {
-        triangles: vec![
+    let polyhedron = PolyhedronObject::new(
+        vec![
             Triangle::new(vertices[0], vertices[2], vertices[1],),
             Triangle::new(vertices[0], vertices[3], vertices[2],),
             Triangle::new(vertices[0], vertices[1], vertices[3],),
             Triangle::new(vertices[1], vertices[2], vertices[3],),
         ],
-        material: Material {
+        Material {
             color: Color::black(),
             reflective: 0.8,
             specular: 300,
             transparency: Some(1.02),
         },
-    };
+    );
 
     scene.add_object(Box::new(polyhedron));
 
@@ -289,7 +285,7 @@ fn rasterization() {
     }));
     scene.add_light(Box::new(DirectionalLight {
         intensity: 0.2,
-        direction: (-1., 0., 1., 0.).into(),
+        direction: (-1., 0., 1.).into(),
     }));
     scene.add_light(Box::new(PointLight {
         intensity: 0.6,
@@ -401,7 +397,7 @@ fn compare() {
     }));
     scene.add_light(Box::new(DirectionalLight {
         intensity: 0.2,
-        direction: (1., 4., 4., 0.).into(),
+        direction: (1., 4., 4.).into(),
     }));
 
     scene.set_camera(
@@ -0,0 +1,255 @@
+use std::ops::RangeInclusive;
+use crate::{EPS, components::*};
+
+const LEAF_SIZE: usize = 4;
+const STACK_SIZE: usize = 64;
+
+struct BvhNode {
+    bounds: BoundingBox,
+    start: usize,
+    count: usize,
+    axis: usize,
+    right: usize,
+}
+
+// Nodes are stored depth first, so the left child of an interior node always
+// directly follows it and only the right child index needs to be kept.
+pub(crate) struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+struct RaySlabs {
+    origin: [f64; 3],
+    inverse_direction: [f64; 3],
+}
+
+impl RaySlabs {
+    fn new(ray: &Ray) -> Self {
+        Self {
+            origin: [ray.origin.x(), ray.origin.y(), ray.origin.z()],
+            inverse_direction: [1. / ray.direction.x(), 1. / ray.direction.y(), 1. / ray.direction.z()],
+        }
+    }
+
+    fn overlaps(&self, bounds: &BoundingBox, t_min: f64, t_max: f64) -> bool {
+        let min = [bounds.min.x(), bounds.min.y(), bounds.min.z()];
+        let max = [bounds.max.x(), bounds.max.y(), bounds.max.z()];
+        let mut t1 = t_min;
+        let mut t2 = t_max;
+        for axis in 0..3 {
+            let a = (min[axis] - self.origin[axis]) * self.inverse_direction[axis];
+            let b = (max[axis] - self.origin[axis]) * self.inverse_direction[axis];
+            // a ray parallel to the slab with its origin on a boundary gives NaN,
+            // and such a ray never leaves the slab
+            if a.is_nan() || b.is_nan() {
+                continue;
+            }
+            let (near, far) = if a <= b { (a, b) } else { (b, a) };
+            if near > t1 {
+                t1 = near;
+            }
+            if far < t2 {
+                t2 = far;
+            }
+        }
+        t1 <= t2
+    }
+}
+
+impl Bvh {
+    pub(crate) fn new(bounds: Vec<Option<BoundingBox>>) -> Self {
+        let mut unbounded = Vec::new();
+        let mut primitives = Vec::new();
+        for (i, b) in bounds.into_iter().enumerate() {
+            // infinite or NaN bounds would poison the split, so such
+            // primitives are tested on every ray like unbounded ones
+            match b {
+                Some(b) if b.is_empty() => (),
+                Some(b) if b.is_finite() => primitives.push((i, b.expanded(EPS))),
+                _ => unbounded.push(i),
+            }
+        }
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            indices: Vec::with_capacity(primitives.len()),
+            unbounded,
+        };
+        if !primitives.is_empty() {
+            bvh.build(&mut primitives);
+        }
+        bvh
+    }
+
+    fn build(&mut self, primitives: &mut [(usize, BoundingBox)]) -> usize {
+        let node = self.nodes.len();
+        let bounds = primitives.iter().fold(BoundingBox::empty(), |acc, p| acc.union(&p.1));
+        self.nodes.push(BvhNode {
+            bounds,
+            start: self.indices.len(),
+            count: 0,
+            axis: 0,
+            right: 0,
+        });
+        if primitives.len() <= LEAF_SIZE {
+            self.indices.extend(primitives.iter().map(|p| p.0));
+            self.nodes[node].count = primitives.len();
+            return node;
+        }
+
+        let centers = primitives.iter().fold(BoundingBox::empty(), |acc, p| {
+            let c = p.1.center();
+            acc.union(&BoundingBox::new(c, c))
+        });
+        let size = centers.size();
+        let axis = if size.x() >= size.y() && size.x() >= size.z() {
+            0
+        } else if size.y() >= size.z() {
+            1
+        } else {
+            2
+        };
+        let mid = primitives.len() / 2;
+        primitives.select_nth_unstable_by(mid, |a, b| {
+            a.1.center()[axis].total_cmp(&b.1.center()[axis])
+                .then(a.0.cmp(&b.0))
+        });
+        let (left, right) = primitives.split_at_mut(mid);
+        self.build(left);
+        let right = self.build(right);
+        self.nodes[node].axis = axis;
+        self.nodes[node].right = right;
+        node
+    }
+
+    // Returns the closest hit reported by `hit_test`, ties going to the
+    // primitive with the lowest index, exactly like a linear scan would.
+    pub(crate) fn closest_hit<T, F>(&self, ray: &Ray, t_range: &RangeInclusive<f64>, mut hit_test: F) -> Option<T>
+        where F: FnMut(usize) -> Option<(f64, T)>
+    {
+        let mut best: Option<(f64, usize, T)> = None;
+        let mut consider = |i: usize, best: &mut Option<(f64, usize, T)>| {
+            if let Some((t, hit)) = hit_test(i) {
+                let closer = match best {
+                    None => true,
+                    Some((best_t, best_i, _)) => t < *best_t || (t == *best_t && i < *best_i),
+                };
+                if closer {
+                    *best = Some((t, i, hit));
+                }
+            }
+        };
+
+        for &i in self.unbounded.iter() {
+            consider(i, &mut best);
+        }
+
+        let slabs = RaySlabs::new(ray);
+        let mut stack = [0usize; STACK_SIZE];
+        let mut top = 0;
+        if !self.nodes.is_empty() {
+            stack[0] = 0;
+            top = 1;
+        }
+        while top > 0 {
+            top -= 1;
+            let node = &self.nodes[stack[top]];
+            let t_max = best.as_ref().map_or(*t_range.end(), |b| b.0.min(*t_range.end()));
+            if !slabs.overlaps(&node.bounds, *t_range.start(), t_max) {
+                continue;
+            }
+            if node.count > 0 {
+                for &i in self.indices[node.start..node.start + node.count].iter() {
+                    consider(i, &mut best);
+                }
+            } else {
+                let left = stack[top] + 1;
+                let (near, far) = if ray.direction[node.axis] < 0. {
+                    (node.right, left)
+                } else {
+                    (left, node.right)
+                };
+                stack[top] = far;
+                stack[top + 1] = near;
+                top += 2;
+            }
+        }
+
+        best.map(|b| b.2)
+    }
+
+    // Stops at the first primitive for which `hit_test` reports a hit.
+    pub(crate) fn any_hit<F>(&self, ray: &Ray, t_range: &RangeInclusive<f64>, mut hit_test: F) -> bool
+        where F: FnMut(usize) -> bool
+    {
+        if self.unbounded.iter().any(|&i| hit_test(i)) {
+            return true;
+        }
+
+        let slabs = RaySlabs::new(ray);
+        let mut stack = [0usize; STACK_SIZE];
+        let mut top = 0;
+        if !self.nodes.is_empty() {
+            stack[0] = 0;
+            top = 1;
+        }
+        while top > 0 {
+            top -= 1;
+            let node = &self.nodes[stack[top]];
+            if !slabs.overlaps(&node.bounds, *t_range.start(), *t_range.end()) {
+                continue;
+            }
+            if node.count > 0 {
+                if self.indices[node.start..node.start + node.count].iter().any(|&i| hit_test(i)) {
+                    return true;
+                }
+            } else {
+                let left = stack[top] + 1;
+                stack[top] = node.right;
+                stack[top + 1] = left;
+                top += 2;
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_bounds_are_tested_on_every_ray() {
+        let infinite = BoundingBox::new(
+            (-f64::INFINITY, -f64::INFINITY, -f64::INFINITY).into(),
+            (f64::INFINITY, f64::INFINITY, f64::INFINITY).into(),
+        );
+        let half_space = BoundingBox::new(
+            (-f64::INFINITY, -f64::INFINITY, 0.).into(),
+            (f64::INFINITY, f64::INFINITY, f64::INFINITY).into(),
+        );
+        let mut bounds: Vec<Option<BoundingBox>> = (0..6)
+            .map(|i| Some(if i % 2 == 0 { infinite } else { half_space }))
+            .collect();
+        for i in 0..8 {
+            let z = 10. + i as f64;
+            bounds.push(Some(BoundingBox::new((-1., -1., z).into(), (1., 1., z + 0.5).into())));
+        }
+        let bvh = Bvh::new(bounds);
+        let ray = Ray { origin: Point::origin(), direction: (0., 0., 1.).into() };
+
+        let mut tested = Vec::new();
+        bvh.closest_hit(&ray, &(0.0..=f64::INFINITY), |i| {
+            tested.push(i);
+            None::<(f64, ())>
+        });
+        for i in 0..6 {
+            assert!(tested.contains(&i));
+        }
+
+        let hit = bvh.closest_hit(&ray, &(0.0..=f64::INFINITY), |i| if i == 3 { Some((5., i)) } else { None });
+        assert_eq!(hit, Some(3));
+    }
+}
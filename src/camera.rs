@@ -0,0 +1,194 @@
+use std::f64::consts::PI;
+use crate::{EPS, components::*};
+
+/// Turns a point on the canvas into the ray traced through it.
+pub trait Camera {
+    /// `x` and `y` run from -0.5 to 0.5 across the canvas, with `y` pointing
+    /// up, and `aspect` is the canvas width over its height.
+    fn ray(&self, x: f64, y: f64, aspect: f64) -> Ray;
+
+    /// Like `ray`, but leaving from the point `lens` of the camera's lens,
+    /// given as a sample of the unit square. Cameras without a lens ignore it.
+    fn lens_ray(&self, x: f64, y: f64, aspect: f64, lens: (f64, f64)) -> Ray {
+        let _ = lens;
+        self.ray(x, y, aspect)
+    }
+
+    /// Smallest `t` a primary ray may hit at. Rays starting on the camera
+    /// only skip a sliver in front of it.
+    fn near_t(&self) -> f64 {
+        EPS
+    }
+}
+
+/// Maps the unit square onto the unit disk, keeping evenly spread samples
+/// evenly spread.
+fn concentric_disk(u: f64, v: f64) -> (f64, f64) {
+    let a = 2. * u - 1.;
+    let b = 2. * v - 1.;
+    if a == 0. && b == 0. {
+        return (0., 0.);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4. * b / a)
+    } else {
+        (b, PI / 2. - PI / 4. * a / b)
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Pinhole camera looking along +z of `rotation`, with the viewport at
+/// `distance` in front of it. This is the camera the rasterizer uses.
+#[derive(Debug, Clone, Copy)]
+pub struct PerspectiveCamera {
+    pub position: Point,
+    pub rotation: Matrix,
+    pub distance: f64,
+    pub viewport_width: f64,
+    pub viewport_height: f64,
+    /// Vertical field of view in degrees. When set it replaces the viewport
+    /// size, whose width then follows the canvas's aspect ratio.
+    pub field_of_view: Option<f64>,
+    /// Diameter of the lens. Zero makes a pinhole with everything in focus.
+    pub aperture: f64,
+    /// Distance along the view direction at which things are in focus.
+    pub focus_distance: f64,
+}
+
+impl PerspectiveCamera {
+    pub fn new(viewport_width: f64, viewport_height: f64) -> Self {
+        Self {
+            position: Point::origin(),
+            rotation: Matrix::identity(),
+            distance: 1.,
+            viewport_width,
+            viewport_height,
+            field_of_view: None,
+            aperture: 0.,
+            focus_distance: 1.,
+        }
+    }
+
+    /// Camera at `eye` looking towards `target`, with `up` pointing up on
+    /// screen as far as possible and a vertical field of view of `fov` degrees.
+    /// It is focused on `target`. Looking along `up` leaves the roll to
+    /// `Quaternion::look_rotation`. Panics if `eye` and `target` coincide.
+    pub fn look_at(eye: Point, target: Point, up: Vector, fov: f64) -> Self {
+        assert!((target - eye).length() > 0., "look_at needs a target apart from the eye");
+        Self {
+            position: eye,
+            rotation: Quaternion::look_rotation(target - eye, up).into(),
+            distance: 1.,
+            viewport_width: 1.,
+            viewport_height: 1.,
+            field_of_view: Some(fov),
+            aperture: 0.,
+            focus_distance: (target - eye).length(),
+        }
+    }
+
+    pub fn viewport_size(&self, aspect: f64) -> (f64, f64) {
+        match self.field_of_view {
+            Some(fov) => {
+                let height = 2. * self.distance * (fov.to_radians() / 2.).tan();
+                (height * aspect, height)
+            },
+            None => (self.viewport_width, self.viewport_height),
+        }
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn ray(&self, x: f64, y: f64, aspect: f64) -> Ray {
+        let (viewport_width, viewport_height) = self.viewport_size(aspect);
+        Ray {
+            origin: self.position,
+            direction: self.rotation * Vector::from((x * viewport_width, y * viewport_height, self.distance)),
+        }
+    }
+
+    fn lens_ray(&self, x: f64, y: f64, aspect: f64, lens: (f64, f64)) -> Ray {
+        let ray = self.ray(x, y, aspect);
+        if self.aperture <= 0. {
+            return ray;
+        }
+        let focus = ray.origin + ray.direction * (self.focus_distance / self.distance);
+        let (u, v) = concentric_disk(lens.0, lens.1);
+        let radius = self.aperture / 2.;
+        let origin = ray.origin + self.rotation * Vector::from((u * radius, v * radius, 0.));
+        // scaled so that `t` = 1 stays on the viewport plane, as for the
+        // pinhole ray
+        Ray {
+            origin,
+            direction: (focus - origin) * (self.distance / self.focus_distance),
+        }
+    }
+
+    // `t` = 1 is on the viewport, and nothing in front of it is drawn, like
+    // the rasterizer's near clipping plane
+    fn near_t(&self) -> f64 {
+        1.
+    }
+}
+
+/// Parallel rays along +z of `rotation`, from a view `height` units tall
+/// centered on `position`. Its width follows the canvas's aspect ratio.
+#[derive(Debug, Clone, Copy)]
+pub struct OrthographicCamera {
+    pub position: Point,
+    pub rotation: Matrix,
+    pub height: f64,
+}
+
+impl Camera for OrthographicCamera {
+    fn ray(&self, x: f64, y: f64, aspect: f64) -> Ray {
+        Ray {
+            origin: self.position + self.rotation * Vector::from((x * self.height * aspect, y * self.height, 0.)),
+            direction: self.rotation * Vector::from((0., 0., 1.)),
+        }
+    }
+}
+
+/// Equidistant fisheye around +z of `rotation`. The angle from the view
+/// direction grows linearly with the distance from the canvas center, up to
+/// half of `field_of_view` degrees at the top and bottom edges.
+#[derive(Debug, Clone, Copy)]
+pub struct FisheyeCamera {
+    pub position: Point,
+    pub rotation: Matrix,
+    pub field_of_view: f64,
+}
+
+impl Camera for FisheyeCamera {
+    fn ray(&self, x: f64, y: f64, aspect: f64) -> Ray {
+        let x = x * aspect;
+        let theta = (x * x + y * y).sqrt() * self.field_of_view.to_radians();
+        let phi = y.atan2(x);
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        Ray {
+            origin: self.position,
+            direction: self.rotation * Vector::from((sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)),
+        }
+    }
+}
+
+/// Full 360° by 180° panorama, with longitude across the canvas and latitude
+/// up it, centered on +z of `rotation`. The canvas should be twice as wide as
+/// it is tall.
+#[derive(Debug, Clone, Copy)]
+pub struct EquirectangularCamera {
+    pub position: Point,
+    pub rotation: Matrix,
+}
+
+impl Camera for EquirectangularCamera {
+    fn ray(&self, x: f64, y: f64, _aspect: f64) -> Ray {
+        let (sin_longitude, cos_longitude) = (x * 2. * PI).sin_cos();
+        let (sin_latitude, cos_latitude) = (y * PI).sin_cos();
+        Ray {
+            origin: self.position,
+            direction: self.rotation * Vector::from((cos_latitude * sin_longitude, sin_latitude, cos_latitude * cos_longitude)),
+        }
+    }
+}
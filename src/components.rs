@@ -1,4 +1,4 @@
-use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::ops::{Add, Div, Index, Mul, Neg, Sub};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Color {
@@ -79,10 +79,53 @@ impl Color {
         )
     }
 
+    /// Color given in sRGB, as picked from an image or a color picker.
+    pub fn from_srgb(r: u8, g: u8, b: u8) -> Self {
+        Color {
+            r: r as f32,
+            g: g as f32,
+            b: b as f32,
+        }.srgb_to_linear()
+    }
+
+    pub fn map(&self, f: impl Fn(f32) -> f32) -> Self {
+        Color {
+            r: f(self.r),
+            g: f(self.g),
+            b: f(self.b),
+        }
+    }
+
+    /// Encodes a linear color with the sRGB transfer curve, keeping the 0-255
+    /// scale. Negative components become 0.
+    pub fn linear_to_srgb(&self) -> Self {
+        self.map(|c| {
+            let c = (c / 255.).max(0.);
+            let c = if c <= 0.0031308 {
+                12.92 * c
+            } else {
+                1.055 * c.powf(1. / 2.4) - 0.055
+            };
+            c * 255.
+        })
+    }
+
+    /// Inverse of `linear_to_srgb`.
+    pub fn srgb_to_linear(&self) -> Self {
+        self.map(|c| {
+            let c = (c / 255.).max(0.);
+            let c = if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            };
+            c * 255.
+        })
+    }
 }
 
 fn clamp_color_component_f(value: f32) -> u8 {
-    value.round().min(255.).max(0.) as u8
+    value.round().clamp(0., 255.) as u8
 }
 
 impl Add<Color> for Color {
@@ -266,12 +309,235 @@ impl From<(f64, f64, f64)> for HomogeneousCoordinate {
     }
 }
 
-pub type Point = HomogeneousCoordinate;
-pub type Vector = HomogeneousCoordinate;
+/// Position in space, `w = 1` in homogeneous form.
+#[derive(Debug, Clone, Copy)]
+pub struct Point(f64, f64, f64);
+
+impl Point {
+    pub const fn origin() -> Self {
+        Self(0., 0., 0.)
+    }
+
+    pub fn x(&self) -> f64 {
+        self.0
+    }
+
+    pub fn y(&self) -> f64 {
+        self.1
+    }
+
+    pub fn z(&self) -> f64 {
+        self.2
+    }
+
+    pub fn set_x(&mut self, x: f64) {
+        self.0 = x;
+    }
+
+    pub fn set_y(&mut self, y: f64) {
+        self.1 = y;
+    }
+
+    pub fn set_z(&mut self, z: f64) {
+        self.2 = z;
+    }
+
+    pub fn midpoint(&self, rhs: &Self) -> Self {
+        *self + (*rhs - *self) / 2.
+    }
+}
+
+impl Sub for Point {
+    type Output = Vector;
+
+    fn sub(self, rhs: Self) -> Vector {
+        Vector(self.0 - rhs.0, self.1 - rhs.1, self.2 - rhs.2)
+    }
+}
+
+impl Add<Vector> for Point {
+    type Output = Self;
+
+    fn add(self, rhs: Vector) -> Self {
+        Self(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
+    }
+}
+
+impl Sub<Vector> for Point {
+    type Output = Self;
+
+    fn sub(self, rhs: Vector) -> Self {
+        Self(self.0 - rhs.0, self.1 - rhs.1, self.2 - rhs.2)
+    }
+}
+
+impl Index<usize> for Point {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("axis out of range: {}", axis),
+        }
+    }
+}
+
+impl From<[f64; 3]> for Point {
+    fn from(value: [f64; 3]) -> Self {
+        Self(value[0], value[1], value[2])
+    }
+}
+
+impl From<(f64, f64, f64)> for Point {
+    fn from(value: (f64, f64, f64)) -> Self {
+        Self(value.0, value.1, value.2)
+    }
+}
+
+/// Divides by `w`, unless it is 0.
+impl From<HomogeneousCoordinate> for Point {
+    fn from(value: HomogeneousCoordinate) -> Self {
+        let value = value.canonical();
+        Self(value.0, value.1, value.2)
+    }
+}
+
+impl From<Point> for HomogeneousCoordinate {
+    fn from(value: Point) -> Self {
+        Self(value.0, value.1, value.2, 1.)
+    }
+}
+
+/// Direction or offset, `w = 0` in homogeneous form.
+#[derive(Debug, Clone, Copy)]
+pub struct Vector(f64, f64, f64);
+
+impl Vector {
+    pub fn x(&self) -> f64 {
+        self.0
+    }
+
+    pub fn y(&self) -> f64 {
+        self.1
+    }
+
+    pub fn z(&self) -> f64 {
+        self.2
+    }
+
+    pub fn dot(&self, rhs: &Self) -> f64 {
+        self.0 * rhs.0 + self.1 * rhs.1 + self.2 * rhs.2
+    }
+
+    pub fn cross(&self, rhs: &Self) -> Self {
+        Self(
+            self.1 * rhs.2 - self.2 * rhs.1,
+            self.2 * rhs.0 - self.0 * rhs.2,
+            self.0 * rhs.1 - self.1 * rhs.0,
+        )
+    }
+
+    pub fn cos(&self, rhs: &Self) -> f64 {
+        let d = self.dot(rhs);
+        d / (self.length() * rhs.length())
+    }
+
+    pub fn reflect(&self, rhs: &Self) -> Self {
+        *self * self.dot(rhs) * 2. - *rhs
+    }
+
+    pub fn length(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+}
+
+impl Add for Vector {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
+    }
+}
+
+impl Sub for Vector {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0 - rhs.0, self.1 - rhs.1, self.2 - rhs.2)
+    }
+}
+
+impl Mul<f64> for Vector {
+    type Output = Self;
+
+    fn mul(self, rhs: f64) -> Self {
+        Self(self.0 * rhs, self.1 * rhs, self.2 * rhs)
+    }
+}
+
+impl Div<f64> for Vector {
+    type Output = Self;
+
+    fn div(self, rhs: f64) -> Self {
+        self * (1. / rhs)
+    }
+}
+
+impl Neg for Vector {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(-self.0, -self.1, -self.2)
+    }
+}
+
+impl Index<usize> for Vector {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("axis out of range: {}", axis),
+        }
+    }
+}
+
+impl From<[f64; 3]> for Vector {
+    fn from(value: [f64; 3]) -> Self {
+        Self(value[0], value[1], value[2])
+    }
+}
+
+impl From<(f64, f64, f64)> for Vector {
+    fn from(value: (f64, f64, f64)) -> Self {
+        Self(value.0, value.1, value.2)
+    }
+}
+
+/// Drops `w`.
+impl From<HomogeneousCoordinate> for Vector {
+    fn from(value: HomogeneousCoordinate) -> Self {
+        Self(value.0, value.1, value.2)
+    }
+}
+
+impl From<Vector> for HomogeneousCoordinate {
+    fn from(value: Vector) -> Self {
+        Self(value.0, value.1, value.2, 0.)
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct HomogeneousMatrix([[f64; 4];4]);
 
+// Pivots smaller than this times the largest entry count as zero, so
+// near-singular matrices are reported instead of giving huge inverses.
+const SINGULAR_TOLERANCE: f64 = 1e-12;
+
 impl HomogeneousMatrix {
     pub fn new(mat: [[f64; 4]; 4]) -> Self {
         Self(mat)
@@ -295,6 +561,15 @@ impl HomogeneousMatrix {
         ])
     }
 
+    pub fn scale_xyz(sx: f64, sy: f64, sz: f64) -> Self {
+        Self([
+            [sx, 0., 0., 0.],
+            [0., sy, 0., 0.],
+            [0., 0., sz, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
     pub fn translation(tx: f64, ty: f64, tz: f64) -> Self {
         Self([
             [1., 0., 0., tx],
@@ -356,6 +631,113 @@ impl HomogeneousMatrix {
             [mat[0][3], mat[1][3], mat[2][3], mat[3][3]],
         ])
     }
+
+    /// Pivots of the linear part are measured against the linear part and the
+    /// last one against the w row, so a large translation can't make a small
+    /// scale look singular.
+    fn pivot_tolerance(&self, column: usize) -> f64 {
+        let magnitude = if column < 3 {
+            self.0[..3].iter().flat_map(|row| &row[..3]).fold(0f64, |max, v| max.max(v.abs()))
+        } else {
+            self.0[3].iter().fold(0f64, |max, v| max.max(v.abs()))
+        };
+        magnitude * SINGULAR_TOLERANCE
+    }
+
+    /// 0 when the matrix is singular, within the same tolerance as `inverse`.
+    #[allow(clippy::needless_range_loop)]
+    pub fn determinant(&self) -> f64 {
+        let mut mat = self.0;
+        let mut determinant = 1.;
+        for i in 0..4 {
+            let mut pivot = i;
+            for j in (i + 1)..4 {
+                if mat[j][i].abs() > mat[pivot][i].abs() {
+                    pivot = j;
+                }
+            }
+            if mat[pivot][i].abs() <= self.pivot_tolerance(i) {
+                return 0.;
+            }
+            if pivot != i {
+                mat.swap(i, pivot);
+                determinant = -determinant;
+            }
+            determinant *= mat[i][i];
+            for j in (i + 1)..4 {
+                let c = mat[j][i] / mat[i][i];
+                for k in i..4 {
+                    mat[j][k] -= c * mat[i][k];
+                }
+            }
+        }
+        determinant
+    }
+
+    /// `None` when the matrix is singular or so close to it that the inverse
+    /// would be dominated by rounding errors.
+    #[allow(clippy::needless_range_loop)]
+    pub fn inverse(&self) -> Option<Self> {
+        let mut mat = self.0;
+        let mut inverse = Self::identity().0;
+        for i in 0..4 {
+            let mut pivot = i;
+            for j in (i + 1)..4 {
+                if mat[j][i].abs() > mat[pivot][i].abs() {
+                    pivot = j;
+                }
+            }
+            if mat[pivot][i].abs() <= self.pivot_tolerance(i) {
+                return None;
+            }
+            mat.swap(i, pivot);
+            inverse.swap(i, pivot);
+            let c = mat[i][i];
+            for j in 0..4 {
+                mat[i][j] /= c;
+                inverse[i][j] /= c;
+            }
+            for j in 0..4 {
+                if j == i {
+                    continue;
+                }
+                let c = mat[j][i];
+                for k in 0..4 {
+                    mat[j][k] -= c * mat[i][k];
+                    inverse[j][k] -= c * inverse[i][k];
+                }
+            }
+        }
+        Some(Self(inverse))
+    }
+
+    /// Inverse transpose of the linear part, for transforming normals so they
+    /// stay perpendicular to surfaces under non-uniform scale. Vectors keep
+    /// `w` at zero. `None` when the matrix is singular.
+    pub fn normal_matrix(&self) -> Option<Self> {
+        let mut linear = self.0;
+        for row in linear.iter_mut().take(3) {
+            row[3] = 0.;
+        }
+        linear[3] = [0., 0., 0., 1.];
+        Some(Self(linear).inverse()?.transposed())
+    }
+}
+
+impl Mul<Point> for HomogeneousMatrix {
+    type Output = Point;
+
+    fn mul(self, rhs: Point) -> Point {
+        self.dot(&rhs.into()).into()
+    }
+}
+
+impl Mul<Vector> for HomogeneousMatrix {
+    type Output = Vector;
+
+    fn mul(self, rhs: Vector) -> Vector {
+        self.dot(&rhs.into()).into()
+    }
 }
 
 impl Mul for HomogeneousMatrix {
@@ -378,6 +760,191 @@ impl Mul for HomogeneousMatrix {
 
 pub type Matrix = HomogeneousMatrix;
 
+/// Unit quaternion describing a rotation. Angles are in degrees, like the
+/// `Matrix::rotation_*` constructors.
+#[derive(Debug, Clone, Copy)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub const fn identity() -> Self {
+        Self {
+            w: 1.,
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        }
+    }
+
+    pub fn from_axis_angle(axis: Vector, deg: f64) -> Self {
+        let axis = axis / axis.length();
+        let (sin, cos) = (deg.to_radians() / 2.).sin_cos();
+        Self {
+            w: cos,
+            x: axis.x() * sin,
+            y: axis.y() * sin,
+            z: axis.z() * sin,
+        }
+    }
+
+    /// Same rotation as `Matrix::compose(vec![rotation_x(x), rotation_y(y), rotation_z(z)])`.
+    pub fn from_euler(x: f64, y: f64, z: f64) -> Self {
+        Self::from_axis_angle((1., 0., 0.).into(), x)
+            * Self::from_axis_angle((0., 1., 0.).into(), y)
+            * Self::from_axis_angle((0., 0., 1.).into(), z)
+    }
+
+    /// Rotation turning the camera's view direction, +z, towards `forward`,
+    /// with +y as close to `up` as possible. When `up` is parallel to
+    /// `forward` the world axis furthest from `forward` stands in for it.
+    pub fn look_rotation(forward: Vector, up: Vector) -> Self {
+        let forward = forward / forward.length();
+        let mut right = up.cross(&forward);
+        if right.length() <= 1e-9 * up.length() {
+            let (x, y, z) = (forward.x().abs(), forward.y().abs(), forward.z().abs());
+            let axis: Vector = if x <= y && x <= z {
+                (1., 0., 0.)
+            } else if y <= z {
+                (0., 1., 0.)
+            } else {
+                (0., 0., 1.)
+            }.into();
+            right = axis.cross(&forward);
+        }
+        let right = right / right.length();
+        let up = forward.cross(&right);
+        HomogeneousMatrix([
+            [right.x(), up.x(), forward.x(), 0.],
+            [right.y(), up.y(), forward.y(), 0.],
+            [right.z(), up.z(), forward.z(), 0.],
+            [0., 0., 0., 1.],
+        ]).into()
+    }
+
+    pub fn dot(&self, rhs: &Self) -> f64 {
+        self.w * rhs.w + self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn length(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalized(&self) -> Self {
+        let l = self.length();
+        Self {
+            w: self.w / l,
+            x: self.x / l,
+            y: self.y / l,
+            z: self.z / l,
+        }
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Self {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    /// Interpolates along the shortest arc, from `self` at `t = 0` to `rhs` at `t = 1`.
+    pub fn slerp(&self, rhs: &Self, t: f64) -> Self {
+        let mut cos = self.dot(rhs);
+        let rhs = if cos < 0. {
+            cos = -cos;
+            Self { w: -rhs.w, x: -rhs.x, y: -rhs.y, z: -rhs.z }
+        } else {
+            *rhs
+        };
+        let (a, b) = if cos > 0.9995 {
+            // nearly parallel, where linear interpolation is accurate and sin(angle) is not
+            (1. - t, t)
+        } else {
+            let angle = cos.acos();
+            let sin = angle.sin();
+            (((1. - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+        Self {
+            w: self.w * a + rhs.w * b,
+            x: self.x * a + rhs.x * b,
+            y: self.y * a + rhs.y * b,
+            z: self.z * a + rhs.z * b,
+        }.normalized()
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        }
+    }
+}
+
+impl From<Quaternion> for HomogeneousMatrix {
+    fn from(q: Quaternion) -> Self {
+        let Quaternion { w, x, y, z } = q.normalized();
+        Self([
+            [1. - 2. * (y * y + z * z), 2. * (x * y - w * z), 2. * (x * z + w * y), 0.],
+            [2. * (x * y + w * z), 1. - 2. * (x * x + z * z), 2. * (y * z - w * x), 0.],
+            [2. * (x * z - w * y), 2. * (y * z + w * x), 1. - 2. * (x * x + y * y), 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+}
+
+/// Takes the rotation of a matrix without scale or shear.
+impl From<HomogeneousMatrix> for Quaternion {
+    fn from(m: HomogeneousMatrix) -> Self {
+        let m = &m.0;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0. {
+            let s = (trace + 1.).sqrt() * 2.;
+            Self {
+                w: s / 4.,
+                x: (m[2][1] - m[1][2]) / s,
+                y: (m[0][2] - m[2][0]) / s,
+                z: (m[1][0] - m[0][1]) / s,
+            }
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (1. + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.;
+            Self {
+                w: (m[2][1] - m[1][2]) / s,
+                x: s / 4.,
+                y: (m[0][1] + m[1][0]) / s,
+                z: (m[0][2] + m[2][0]) / s,
+            }
+        } else if m[1][1] > m[2][2] {
+            let s = (1. + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.;
+            Self {
+                w: (m[0][2] - m[2][0]) / s,
+                x: (m[0][1] + m[1][0]) / s,
+                y: s / 4.,
+                z: (m[1][2] + m[2][1]) / s,
+            }
+        } else {
+            let s = (1. + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.;
+            Self {
+                w: (m[1][0] - m[0][1]) / s,
+                x: (m[0][2] + m[2][0]) / s,
+                y: (m[1][2] + m[2][1]) / s,
+                z: s / 4.,
+            }
+        };
+        q.normalized()
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Point,
@@ -405,6 +972,11 @@ impl Sphere {
         let t2 = (-b + discriminant.sqrt()) / (2.0 * a);
         Some((t1, t2))
     }
+
+    pub fn bounding_box(&self) -> BoundingBox {
+        let r: Vector = (self.radius, self.radius, self.radius).into();
+        BoundingBox::new(self.center - r, self.center + r)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -422,21 +994,19 @@ pub struct Triangle {
     pub normal: Vector,
 }
 
+#[allow(clippy::needless_range_loop)]
 fn solve_equations(mut coefficients: [[f64; 3]; 3], mut rhs: [f64; 3]) -> Option<[f64; 3]> {
     for i in 0..3 {
-        if coefficients[i][i] == 0. {
-            for j in (i+1)..3 {
-                if coefficients[j][i] != 0. {
-                    let temp = coefficients[i];
-                    coefficients[i] = coefficients[j];
-                    coefficients[j] = temp;
-                    let temp = rhs[i];
-                    rhs[i] = rhs[j];
-                    rhs[j] = temp;
-                }
-                break;
+        let mut pivot = i;
+        for j in (i + 1)..3 {
+            if coefficients[j][i].abs() > coefficients[pivot][i].abs() {
+                pivot = j;
             }
         }
+        if pivot != i {
+            coefficients.swap(i, pivot);
+            rhs.swap(i, pivot);
+        }
         if coefficients[i][i] == 0. {
             return None
         }
@@ -464,6 +1034,91 @@ fn solve_equations(mut coefficients: [[f64; 3]; 3], mut rhs: [f64; 3]) -> Option
     Some(rhs)
 }
 
+fn evaluate_polynomial(coefficients: &[f64], x: f64) -> f64 {
+    coefficients.iter().fold(0., |acc, &c| acc * x + c)
+}
+
+// Real roots of the polynomial with `coefficients` from the highest power
+// down, in ascending order. Roots of higher degrees are bracketed between
+// the roots of the derivative, where the polynomial is monotonic, and
+// bisected, which stays accurate where closed-form solutions lose precision.
+// Roots where the polynomial only touches zero may be missed.
+fn solve_polynomial(coefficients: &[f64]) -> Vec<f64> {
+    let coefficients = match coefficients.iter().position(|&c| c != 0.) {
+        Some(i) => &coefficients[i..],
+        None => return Vec::new(),
+    };
+    match *coefficients {
+        [] | [_] => Vec::new(),
+        [a, b] => vec![-b / a],
+        [a, b, c] => {
+            let discriminant = b * b - 4. * a * c;
+            if discriminant < 0. {
+                return Vec::new();
+            }
+            let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+            if q == 0. {
+                return vec![0.];
+            }
+            let (x1, x2) = (q / a, c / q);
+            if x1 < x2 { vec![x1, x2] } else { vec![x2, x1] }
+        },
+        _ => {
+            let degree = coefficients.len() - 1;
+            let derivative: Vec<f64> = coefficients[..degree].iter().enumerate()
+                .map(|(i, &c)| c * (degree - i) as f64)
+                .collect();
+            let bound = 1. + coefficients[1..].iter().map(|c| (c / coefficients[0]).abs()).fold(0., f64::max);
+            let mut ends = vec![-bound];
+            ends.extend(solve_polynomial(&derivative).into_iter().filter(|x| x.abs() < bound));
+            ends.push(bound);
+
+            let mut roots: Vec<f64> = Vec::new();
+            for pair in ends.windows(2) {
+                let (mut lo, mut hi) = (pair[0], pair[1]);
+                let mut f_lo = evaluate_polynomial(coefficients, lo);
+                let f_hi = evaluate_polynomial(coefficients, hi);
+                if f_lo == 0. {
+                    if roots.last() != Some(&lo) {
+                        roots.push(lo);
+                    }
+                    continue;
+                }
+                if f_hi == 0. || (f_lo < 0.) == (f_hi < 0.) {
+                    continue;
+                }
+                loop {
+                    let mid = 0.5 * (lo + hi);
+                    if mid <= lo || mid >= hi {
+                        break;
+                    }
+                    let f_mid = evaluate_polynomial(coefficients, mid);
+                    if f_mid == 0. {
+                        lo = mid;
+                        break;
+                    }
+                    if (f_mid < 0.) == (f_lo < 0.) {
+                        lo = mid;
+                        f_lo = f_mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                roots.push(lo);
+            }
+            if evaluate_polynomial(coefficients, bound) == 0. {
+                roots.push(bound);
+            }
+            roots
+        },
+    }
+}
+
+/// Real roots of `a x^4 + b x^3 + c x^2 + d x + e`, in ascending order.
+pub(crate) fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    solve_polynomial(&[a, b, c, d, e])
+}
+
 impl Triangle {
     pub fn new(a: Point, b: Point, c: Point) -> Triangle {
         let v: Vector = b - a;
@@ -486,8 +1141,11 @@ impl Triangle {
             [ab.y(), ac.y(), -ray.direction.y()],
             [ab.z(), ac.z(), -ray.direction.z()],
         ], [ao.x(), ao.y(), ao.z()])
-        .map(|[r, s, t]| if r < 0. || s < 0. || r + s > 1. { None } else { Some(t) })
-        .flatten()
+        .and_then(|[r, s, t]| if r < 0. || s < 0. || r + s > 1. { None } else { Some(t) })
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::from_points(&[self.a, self.b, self.c])
     }
 }
 
@@ -503,15 +1161,23 @@ impl Plane {
         let w: Vector = c - a;
         let cross = v.cross(&w);
         let normal: Vector = cross / cross.length();
-        let d = -normal.dot(&a);
+        let d = -normal.dot(&(a - Point::origin()));
         Self {
             normal,
             d,
         }
     }
 
+    pub fn from_point_normal(point: Point, normal: Vector) -> Self {
+        let normal: Vector = normal / normal.length();
+        Self {
+            normal,
+            d: -normal.dot(&(point - Point::origin())),
+        }
+    }
+
     pub fn signed_distance(&self, p: &Point) -> f64 {
-        self.normal.dot(p) + self.d
+        self.normal.dot(&(*p - Point::origin())) + self.d
     }
 
     pub fn intersection(&self, a: &Point, b: &Point) -> Option<(f64, Point)> {
@@ -520,8 +1186,225 @@ impl Plane {
         if denom == 0. {
             None
         } else {
-            let t = (-self.d - self.normal.dot(a)) / denom;
+            let t = -self.signed_distance(a) / denom;
             Some((t, *a + ab * t))
         }
     }
-}
\ No newline at end of file
+
+    pub fn compute_ray_intersection(&self, ray: &Ray) -> Option<f64> {
+        let denom = self.normal.dot(&ray.direction);
+        if denom == 0. {
+            None
+        } else {
+            Some(-self.signed_distance(&ray.origin) / denom)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl BoundingBox {
+    pub fn new(min: Point, max: Point) -> Self {
+        Self {
+            min,
+            max,
+        }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: (f64::INFINITY, f64::INFINITY, f64::INFINITY).into(),
+            max: (-f64::INFINITY, -f64::INFINITY, -f64::INFINITY).into(),
+        }
+    }
+
+    pub fn from_points(points: &[Point]) -> Self {
+        points.iter().fold(Self::empty(), |acc, p| acc.union(&Self::new(*p, *p)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x() > self.max.x() || self.min.y() > self.max.y() || self.min.z() > self.max.z()
+    }
+
+    /// Whether every corner coordinate is a finite number.
+    pub fn is_finite(&self) -> bool {
+        [self.min.x(), self.min.y(), self.min.z(), self.max.x(), self.max.y(), self.max.z()]
+            .iter()
+            .all(|v| v.is_finite())
+    }
+
+    pub fn union(&self, rhs: &Self) -> Self {
+        Self {
+            min: (self.min.x().min(rhs.min.x()), self.min.y().min(rhs.min.y()), self.min.z().min(rhs.min.z())).into(),
+            max: (self.max.x().max(rhs.max.x()), self.max.y().max(rhs.max.y()), self.max.z().max(rhs.max.z())).into(),
+        }
+    }
+
+    pub fn intersection(&self, rhs: &Self) -> Self {
+        Self {
+            min: (self.min.x().max(rhs.min.x()), self.min.y().max(rhs.min.y()), self.min.z().max(rhs.min.z())).into(),
+            max: (self.max.x().min(rhs.max.x()), self.max.y().min(rhs.max.y()), self.max.z().min(rhs.max.z())).into(),
+        }
+    }
+
+    pub fn expanded(&self, margin: f64) -> Self {
+        let margin: Vector = (margin, margin, margin).into();
+        Self {
+            min: self.min - margin,
+            max: self.max + margin,
+        }
+    }
+
+    /// Box enclosing this one after `transform` is applied to it.
+    pub fn transformed(&self, transform: &Matrix) -> Self {
+        let pick = |bit: bool, min: f64, max: f64| if bit { max } else { min };
+        let corners: Vec<Point> = (0..8)
+            .map(|i| (
+                pick(i & 1 != 0, self.min.x(), self.max.x()),
+                pick(i & 2 != 0, self.min.y(), self.max.y()),
+                pick(i & 4 != 0, self.min.z(), self.max.z()),
+            ))
+            .map(|p| *transform * Point::from(p))
+            .collect();
+        Self::from_points(&corners)
+    }
+
+    pub fn center(&self) -> Point {
+        self.min.midpoint(&self.max)
+    }
+
+    pub fn size(&self) -> Vector {
+        self.max - self.min
+    }
+
+    pub fn compute_ray_intersection(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let mut t1 = -f64::INFINITY;
+        let mut t2 = f64::INFINITY;
+        for &(origin, direction, min, max) in [
+            (ray.origin.x(), ray.direction.x(), self.min.x(), self.max.x()),
+            (ray.origin.y(), ray.direction.y(), self.min.y(), self.max.y()),
+            (ray.origin.z(), ray.direction.z(), self.min.z(), self.max.z()),
+        ].iter() {
+            if direction == 0. {
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+            let a = (min - origin) / direction;
+            let b = (max - origin) / direction;
+            t1 = t1.max(a.min(b));
+            t2 = t2.min(a.max(b));
+            if t1 > t2 {
+                return None;
+            }
+        }
+        Some((t1, t2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_matrix_eq(a: &Matrix, b: &Matrix) {
+        for (row_a, row_b) in a.0.iter().zip(b.0.iter()) {
+            for (x, y) in row_a.iter().zip(row_b.iter()) {
+                assert!((x - y).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let m = Matrix::compose(vec![
+            Matrix::translation(3., -2., 5.),
+            Matrix::rotation_y(30.),
+            Matrix::rotation_x(-70.),
+            Matrix::scale_xyz(2., 0.5, 4.),
+        ]);
+        let inverse = m.inverse().unwrap();
+        assert_matrix_eq(&(m * inverse), &Matrix::identity());
+        assert_matrix_eq(&(inverse * m), &Matrix::identity());
+    }
+
+    #[test]
+    fn determinant_of_scale_and_rotation() {
+        let m = Matrix::compose(vec![Matrix::rotation_z(40.), Matrix::scale_xyz(2., 3., -4.)]);
+        assert!((m.determinant() + 24.).abs() < 1e-9);
+        assert_eq!(Matrix::scale_xyz(1., 0., 1.).determinant(), 0.);
+    }
+
+    #[test]
+    fn near_singular_matrices_are_reported() {
+        let flat = Matrix::scale_xyz(1., 1., 1e-300);
+        assert!(flat.inverse().is_none());
+        assert!(flat.normal_matrix().is_none());
+        assert_eq!(flat.determinant(), 0.);
+
+        let collinear = Matrix::new([
+            [1., 1., 0., 0.],
+            [0., 1e-14, 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ]);
+        assert!(collinear.inverse().is_none());
+        assert!(collinear.normal_matrix().is_none());
+        assert_eq!(collinear.determinant(), 0.);
+    }
+
+    #[test]
+    fn translation_does_not_hide_a_small_scale() {
+        let m = Matrix::compose(vec![Matrix::translation(1e7, -1e7, 1e7), Matrix::scale(1e-6)]);
+        assert!(m.determinant() > 0.);
+        let inverse = m.inverse().unwrap();
+        // a world position only resolves the local one to about 1e-9 / 1e-6
+        let q = inverse * (m * Point::from((2., 3., -1.)));
+        assert!((q.x() - 2.).abs() < 1e-2 && (q.y() - 3.).abs() < 1e-2 && (q.z() + 1.).abs() < 1e-2, "{:?}", q);
+        assert!(m.normal_matrix().is_some());
+
+        let large = Matrix::scale(1e13);
+        assert!(large.inverse().is_some());
+    }
+
+    #[test]
+    fn normal_matrix_is_the_inverse_transpose_of_the_linear_part() {
+        let m = Matrix::compose(vec![Matrix::translation(10., 0., 0.), Matrix::scale_xyz(2., 1., 1.)]);
+        assert_matrix_eq(&m.normal_matrix().unwrap(), &Matrix::scale_xyz(0.5, 1., 1.));
+
+        let r = Matrix::rotation_y(25.);
+        assert_matrix_eq(&r.normal_matrix().unwrap(), &r);
+    }
+
+    #[test]
+    fn solve_equations_pivots_on_the_largest_coefficient() {
+        // a tiny but non-zero leading coefficient used to be kept as the
+        // pivot, and the huge multiplier it gave wiped out x entirely
+        let [x, y, z] = solve_equations([
+            [1e-20, 1., 0.],
+            [1., 1., 0.],
+            [0., 0., 1.],
+        ], [1., 2., 1.]).unwrap();
+        assert!((x - 1.).abs() < 1e-12);
+        assert!((y - 1.).abs() < 1e-12);
+        assert_eq!(z, 1.);
+    }
+
+    #[test]
+    fn look_rotation_straight_up_and_down() {
+        let up: Vector = (0., 1., 0.).into();
+        for forward in [(0., 1., 0.), (0., -1., 0.), (0., -3., 1e-12), (0., 0., 0.2)] {
+            let forward: Vector = forward.into();
+            let m = Matrix::from(Quaternion::look_rotation(forward, up));
+            assert!(m.0.iter().flatten().all(|v| v.is_finite()), "{:?}", m);
+            assert_matrix_eq(&(m * m.transposed()), &Matrix::identity());
+            assert!((m.determinant() - 1.).abs() < 1e-9);
+            let z = m * Vector::from((0., 0., 1.));
+            assert!((z - forward / forward.length()).length() < 1e-9, "{:?}", z);
+        }
+    }
+}
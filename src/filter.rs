@@ -0,0 +1,125 @@
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy)]
+pub enum FilterKind {
+    BOX,
+    TENT,
+    /// Gaussian falling off as `exp(-2 x^2)`, shifted down to reach zero at
+    /// the radius.
+    GAUSSIAN,
+    /// Mitchell-Netravali with B = C = 1/3, stretched over the radius.
+    MITCHELL,
+    /// Windowed sinc with as many lobes as the radius.
+    LANCZOS,
+}
+
+/// Reconstruction filter deciding how much a sample counts towards each
+/// pixel around it. Samples reach the pixels whose centers are less than
+/// `radius` away along both axes, so a radius above 0.5 lets them spread
+/// into neighbouring pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct PixelFilter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl PixelFilter {
+    /// Weight of a sample at `(dx, dy)` pixels from a pixel's center.
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f64) -> f64 {
+        let r = self.radius;
+        if d < -r || d >= r {
+            return 0.;
+        }
+        let d = d.abs();
+        match self.kind {
+            FilterKind::BOX => 1.,
+            FilterKind::TENT => r - d,
+            FilterKind::GAUSSIAN => (-2. * d * d).exp() - (-2. * r * r).exp(),
+            FilterKind::MITCHELL => {
+                let (b, c) = (1. / 3., 1. / 3.);
+                let x = 2. * d / r;
+                if x < 1. {
+                    ((12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x + (6. - 2. * b)) / 6.
+                } else {
+                    ((-b - 6. * c) * x * x * x + (6. * b + 30. * c) * x * x + (-12. * b - 48. * c) * x + (8. * b + 24. * c)) / 6.
+                }
+            },
+            FilterKind::LANCZOS => sinc(d) * sinc(d / r),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [FilterKind; 5] = [FilterKind::BOX, FilterKind::TENT, FilterKind::GAUSSIAN, FilterKind::MITCHELL, FilterKind::LANCZOS];
+
+    fn filters() -> impl Iterator<Item = PixelFilter> {
+        KINDS.iter().flat_map(|&kind| [0.5, 1., 1.5, 2., 3.].map(|radius| PixelFilter { kind, radius }))
+    }
+
+    #[test]
+    fn weights_vanish_at_and_beyond_the_radius() {
+        for filter in filters() {
+            let r = filter.radius;
+            for d in [r, r + 1e-9, r + 0.5, 2. * r, 100.] {
+                assert_eq!(filter.weight(d, 0.), 0., "{:?} at {}", filter, d);
+                assert_eq!(filter.weight(0., d), 0., "{:?} at {}", filter, d);
+                assert_eq!(filter.weight(-d - 1e-9, 0.), 0., "{:?} at {}", filter, -d);
+            }
+            // the box keeps its lower edge so a sample on the boundary of
+            // two pixels counts towards exactly one of them; the others are
+            // already zero there
+            if !matches!(filter.kind, FilterKind::BOX) {
+                assert!(filter.weight(-r, 0.).abs() < 1e-12, "{:?} at {}", filter, -r);
+            }
+            assert!(filter.weight(0., 0.) > 0.);
+        }
+    }
+
+    #[test]
+    fn weights_are_symmetric() {
+        for filter in filters() {
+            for i in 0..40 {
+                let d = filter.radius * i as f64 / 40.;
+                for e in [0., 0.3 * filter.radius, -0.7 * filter.radius] {
+                    let w = filter.weight(d, e);
+                    for (x, y) in [(-d, e), (d, -e), (-d, -e), (e, d)] {
+                        assert!((filter.weight(x, y) - w).abs() < 1e-12, "{:?} at ({}, {})", filter, d, e);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn normalized_weights_sum_to_one() {
+        // a sample somewhere in pixel (0, 0), spread over the pixels around it
+        for filter in filters() {
+            for (sx, sy) in [(0.5, 0.5), (0.1, 0.8), (0.02, 0.97), (0.99, 0.25)] {
+                let reach = filter.radius.ceil() as i32 + 1;
+                let weights: Vec<f64> = (-reach..=reach)
+                    .flat_map(|py| (-reach..=reach).map(move |px| (px, py)))
+                    .map(|(px, py)| filter.weight(sx - (px as f64 + 0.5), sy - (py as f64 + 0.5)))
+                    .collect();
+                let total: f64 = weights.iter().sum();
+                assert!(total != 0., "{:?} at ({}, {})", filter, sx, sy);
+                let normalized: f64 = weights.iter().map(|w| w / total).sum();
+                assert!((normalized - 1.).abs() < 1e-12);
+            }
+        }
+    }
+}
@@ -1,20 +1,38 @@
+mod bvh;
+mod camera;
 mod components;
+mod filter;
 mod objects;
+mod output;
+mod sampler;
+pub use camera::*;
 pub use components::*;
+pub use filter::*;
 pub use objects::*;
+pub use output::*;
+pub use sampler::*;
+use bvh::Bvh;
 use std::thread;
 use std::mem;
 use std::ops::{RangeInclusive, Sub, Add, Div, Mul};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 pub(crate) const EPS: f64 = 0.001;
 pub(crate) const AIR_REFRACTION_INDEX: f64 = 1.0;
 
 fn interpolate<T>(i0: i32, d0: T, i1: i32, d1: T) -> Vec<T> where T: Sub<T, Output=T> + Add<T, Output=T> + Div<f64, Output=T> + Mul<f64, Output=T> + Clone + Copy {
+    interpolate_between(i0, d0, i1, d1, i0, i1)
+}
+
+/// The values `interpolate` gives for `from..=to` only.
+fn interpolate_between<T>(i0: i32, d0: T, i1: i32, d1: T, from: i32, to: i32) -> Vec<T> where T: Sub<T, Output=T> + Add<T, Output=T> + Div<f64, Output=T> + Mul<f64, Output=T> + Clone + Copy {
     if i0 == i1 {
-        vec![d0]
+        (from..=to).map(|_| d0).collect()
     } else {
         let a = (d1 - d0) / (i1 - i0) as f64;
-        (i0..=i1).into_iter().map(|i| a * (i - i0) as f64 + d0).collect()
+        (from..=to).map(|i| a * (i - i0) as f64 + d0).collect()
     }
 }
 
@@ -25,29 +43,107 @@ pub enum Shading {
     PHONG,
 }
 
+/// When `Canvas::render_progressive` stops.
+#[derive(Debug, Clone, Copy)]
+pub enum RenderBudget {
+    PASSES(u32),
+    TIME(Duration),
+}
+
+/// Traced samples of a pixel, with their position in it.
+type TracedSamples = Vec<((f64, f64), Color)>;
+
+const TILE_SIZE: u32 = 16;
+
+/// Rectangle of pixels rendered as one piece of work.
+#[derive(Debug, Clone, Copy)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    fn pixels(self) -> impl Iterator<Item = (u32, u32)> {
+        (self.y..self.y + self.height).flat_map(move |y| (self.x..self.x + self.width).map(move |x| (x, y)))
+    }
+}
+
+/// Sent to the observer of `Canvas::render_mth_with` after each tile.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderProgress {
+    pub tile: Tile,
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    pub elapsed: Duration,
+    /// Estimated time left, assuming the remaining tiles take as long on
+    /// average as the finished ones.
+    pub eta: Duration,
+}
+
+/// Stops a render from another thread. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 pub struct Canvas {
     width: u32,
     height: u32,
-    image_data: Vec<ColorPack>,
+    /// Colors as rendered, before any clamping to the 0-255 range.
+    image_data: Vec<Color>,
+    /// 8-bit copy of the image for `data`, made when first asked for.
+    packed: OnceLock<Vec<u8>>,
+    output: OutputTransform,
     depth_buffer: Vec<f64>,
+    sample_counts: Vec<u32>,
+    /// Samples of each pixel kept until they have been combined into the image.
+    traced_samples: Vec<TracedSamples>,
+    /// Weighted sum of the samples of each pixel and the sum of their weights,
+    /// over the passes of a progressive render.
+    accumulation: Vec<(Color, f64)>,
+    passes: u32,
 }
 
 impl Canvas {
     pub fn new(width: u32, height: u32, background: Color) -> Canvas {
         assert!(width > 0 && height > 0);
 
-        let image_data = vec![background.clamp(); (width * height) as usize];
+        let image_data = vec![background; (width * height) as usize];
 
         Canvas {
             width,
             height,
             image_data,
+            packed: OnceLock::new(),
+            output: OutputTransform::default(),
             depth_buffer: vec![0.; (width * height) as usize],
+            sample_counts: vec![0; (width * height) as usize],
+            traced_samples: Vec::new(),
+            accumulation: Vec::new(),
+            passes: 0,
         }
     }
 
+    /// Also starts progressive rendering over.
     pub fn clear(&mut self, background: Color) {
-        self.image_data.fill(background.clamp());
+        self.image_data.fill(background);
+        self.packed.take();
+        self.accumulation = Vec::new();
+        self.passes = 0;
     }
 
     pub fn get_width(&self) -> u32 {
@@ -58,6 +154,12 @@ impl Canvas {
         self.height
     }
 
+    /// Number of rays traced for each pixel by the last ray-tracing render,
+    /// row by row like the image.
+    pub fn sample_counts(&self) -> &[u32] {
+        &self.sample_counts
+    }
+
     fn index(&self, x: u32, y: u32) -> usize {
         assert!(x < self.width && y < self.height);
         y as usize * self.width as usize + x as usize
@@ -65,22 +167,18 @@ impl Canvas {
 
     pub fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
         let index = self.index(x, y);
-        self.image_data[index] = color.clamp();
+        self.image_data[index] = color;
+        self.packed.take();
     }
 
-    fn update_depth_buffer(&mut self, x: i32, y: i32, iz: f64) -> bool {
-        let x = x + self.width as i32 / 2;
-        let y = self.height as i32 / 2 - y;
-        if x < 0 || x >= self.width as i32 || y < 0 || y >= self.height as i32 {
-            return false;
-        }
-        let index = self.index(x as u32, y as u32);
-        if self.depth_buffer[index] < iz {
-            self.depth_buffer[index] = iz;
-            true
-        } else {
-            false
-        }
+    pub fn get_pixel(&self, x: u32, y: u32) -> Color {
+        self.image_data[self.index(x, y)]
+    }
+
+    /// The image row by row, with colors as rendered, which may go beyond
+    /// the 0-255 range of `data`.
+    pub fn hdr_data(&self) -> &[Color] {
+        &self.image_data
     }
 
     pub fn put_pixel(&mut self, x: i32, y: i32, color: Color) {
@@ -92,67 +190,556 @@ impl Canvas {
         self.set_pixel(x as u32, y as u32, color);
     }
 
+    /// How `data` converts the image to 8-bit.
+    pub fn set_output_transform(&mut self, output: OutputTransform) {
+        self.output = output;
+        self.packed.take();
+    }
+
+    /// The image as 8-bit RGB, row by row, converted by the output transform.
     pub fn data(&self) -> &[u8] {
-        let p = self.image_data.as_ptr();
-        unsafe {
-            std::slice::from_raw_parts(
-                p as *const u8,
-                self.image_data.len() * std::mem::size_of::<ColorPack>(),
-            )
-        }
+        self.packed.get_or_init(|| {
+            self.image_data.iter()
+                .flat_map(|color| {
+                    let (r, g, b) = self.output.apply(*color);
+                    [r, g, b]
+                })
+                .collect()
+        })
     }
 
     pub fn render(&mut self, scene: &Scene, depth: u32, samples: u32) {
+        self.begin_render(scene);
         let width = self.width;
         let height = self.height;
         for x in 0..width {
             for y in 0..height {
-                self.super_sampling(x, y, samples, scene, depth);
+                let pixel = Self::trace_pixel(scene, (width, height), x, y, samples, depth);
+                self.store_pixel(scene, x, y, pixel);
             }
         }
+        let tiles = self.tiles();
+        self.end_render(scene, &tiles);
     }
 
+    /// Same as `render`, on `threads` threads.
     pub fn render_mth(&mut self, scene: &Scene, threads: u32, depth: u32, samples: u32) {
-        let mut v = Vec::new();
-        for i in 0..threads {
-            let scene = unsafe { Box::new(mem::transmute::<_, &'static Scene>(scene)) };
-            let canvas = unsafe { Box::new(mem::transmute::<_, &'static mut Canvas>(&mut *self)) };
-            let width = self.width;
-            let height = self.height;
-            v.push(thread::spawn(move || {
-                for x in 0..width {
-                    if x % threads != i {
-                        continue;
-                    }
-                    for y in 0..height {
-                        canvas.super_sampling(x, y, samples, &scene, depth);
+        self.render_mth_with(scene, threads, depth, samples, None, None);
+    }
+
+    /// `render_mth` calling `observer` as each tile is finished, and stopping
+    /// soon after `cancel` is cancelled. Returns whether the whole canvas was
+    /// rendered. When it wasn't, the finished tiles are in place and the rest
+    /// of the canvas is left as it was.
+    pub fn render_mth_with(
+        &mut self,
+        scene: &Scene,
+        threads: u32,
+        depth: u32,
+        samples: u32,
+        observer: Option<&mut dyn FnMut(&RenderProgress)>,
+        cancel: Option<&CancelToken>,
+    ) -> bool {
+        self.begin_render(scene);
+        let size = (self.width, self.height);
+        let mut finished_tiles = Vec::new();
+        let finished = self.trace_tiles(
+            threads,
+            |x, y| Self::trace_pixel(scene, size, x, y, samples, depth),
+            |canvas, tile, pixels| {
+                for ((x, y), pixel) in tile.pixels().zip(pixels) {
+                    canvas.store_pixel(scene, x, y, pixel);
+                }
+                finished_tiles.push(tile);
+            },
+            observer,
+            cancel,
+        );
+        self.end_render(scene, &finished_tiles);
+        finished
+    }
+
+    /// The canvas cut into tiles of up to `TILE_SIZE` by `TILE_SIZE` pixels,
+    /// row by row.
+    fn tiles(&self) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y in (0..self.height).step_by(TILE_SIZE as usize) {
+            for x in (0..self.width).step_by(TILE_SIZE as usize) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: TILE_SIZE.min(self.width - x),
+                    height: TILE_SIZE.min(self.height - y),
+                });
+            }
+        }
+        tiles
+    }
+
+    /// Runs `trace` over every pixel on `threads` threads, which take tiles
+    /// from a shared queue as they become free. The results of each finished
+    /// tile are handed to `store` on the calling thread, in `Tile::pixels`
+    /// order, and then reported to `observer`. Tiles being traced when
+    /// `cancel` is cancelled are dropped. Returns whether all tiles were done.
+    fn trace_tiles<T, F, S>(
+        &mut self,
+        threads: u32,
+        trace: F,
+        mut store: S,
+        mut observer: Option<&mut dyn FnMut(&RenderProgress)>,
+        cancel: Option<&CancelToken>,
+    ) -> bool
+        where T: Send, F: Fn(u32, u32) -> T + Sync, S: FnMut(&mut Canvas, Tile, Vec<T>)
+    {
+        let tiles = self.tiles();
+        let next = AtomicUsize::new(0);
+        let cancelled = || cancel.is_some_and(|cancel| cancel.is_cancelled());
+        let (sender, receiver) = mpsc::channel();
+        let start = Instant::now();
+        let mut tiles_done = 0;
+        thread::scope(|s| {
+            for _ in 0..threads.max(1) {
+                let sender = sender.clone();
+                let (tiles, next, trace, cancelled) = (&tiles, &next, &trace, &cancelled);
+                s.spawn(move || {
+                    while let Some(&tile) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let pixels: Option<Vec<T>> = tile.pixels()
+                            .map(|(x, y)| if cancelled() { None } else { Some(trace(x, y)) })
+                            .collect();
+                        let Some(pixels) = pixels else { break };
+                        if sender.send((tile, pixels)).is_err() {
+                            break;
+                        }
                     }
+                });
+            }
+            drop(sender);
+            for (tile, pixels) in receiver {
+                store(self, tile, pixels);
+                tiles_done += 1;
+                if let Some(observer) = observer.as_mut() {
+                    let elapsed = start.elapsed();
+                    observer(&RenderProgress {
+                        tile,
+                        tiles_done,
+                        tiles_total: tiles.len(),
+                        elapsed,
+                        eta: elapsed.mul_f64((tiles.len() - tiles_done) as f64 / tiles_done as f64),
+                    });
                 }
-            }));
+            }
+        });
+        tiles_done == tiles.len()
+    }
+
+    fn begin_render(&mut self, scene: &Scene) {
+        scene.object_bvh();
+        self.traced_samples = match scene.filter {
+            Some(_) => vec![Vec::new(); self.image_data.len()],
+            None => Vec::new(),
+        };
+    }
+
+    /// Resolves the pixels of the `finished` tiles, leaving the rest of the
+    /// canvas alone when a render was cancelled.
+    fn end_render(&mut self, scene: &Scene, finished: &[Tile]) {
+        if let Some(filter) = scene.filter {
+            self.apply_filter(&filter, finished);
+            self.traced_samples = Vec::new();
         }
-        for t in v {
-            t.join().unwrap();
+    }
+
+    /// Sets each pixel of `tiles` to the filter-weighted average of the
+    /// samples around it. Pixels that get no weight keep the average of their
+    /// own samples.
+    fn apply_filter(&mut self, filter: &PixelFilter, tiles: &[Tile]) {
+        for tile in tiles {
+            for (x, y) in tile.pixels() {
+                let (color, weight) = self.filtered(filter, x, y);
+                if weight > 0. {
+                    self.set_pixel(x, y, color * (1. / weight));
+                }
+            }
         }
     }
 
-    fn super_sampling(&mut self, x: u32, y: u32, n: u32, scene: &Scene, depth: u32) {
+    /// Filter-weighted sum of the traced samples around pixel `(x, y)`, and
+    /// the sum of their weights.
+    fn filtered(&self, filter: &PixelFilter, x: u32, y: u32) -> (Color, f64) {
+        let reach = (filter.radius - 0.5).ceil().max(0.) as i64;
+        let (x, y) = (x as i64, y as i64);
         let mut color = Color::black();
-        let width = self.width * n;
-        let height = self.height * n;
-        let half_width = (width / 2) as i32;
-        let half_height = (height / 2) as i32;
-        let xn = (x * n) as i32;
-        let yn = (y * n) as i32;
-        for i in 0..n as i32 {
-            for j in 0..n as i32 {
-                let ray = scene.canvas_to_viewport(xn + i - half_width, half_height - (yn + j), width, height);
-                color = color + scene.trace_ray(&ray, AIR_REFRACTION_INDEX, 1.0..=f64::INFINITY, depth);
+        let mut weight = 0.;
+        for sy in (y - reach).max(0)..=(y + reach).min(self.height as i64 - 1) {
+            for sx in (x - reach).max(0)..=(x + reach).min(self.width as i64 - 1) {
+                for &((px, py), sample) in &self.traced_samples[self.index(sx as u32, sy as u32)] {
+                    let w = filter.weight((sx - x) as f64 + px - 0.5, (sy - y) as f64 + py - 0.5);
+                    color = color + sample * w;
+                    weight += w;
+                }
+            }
+        }
+        (color, weight)
+    }
+
+    /// Renders pass after pass of `samples * samples` rays per pixel until
+    /// `budget` runs out, carrying on from the passes of earlier calls since
+    /// the last `clear`. Stopping and carrying on later gives the same image
+    /// as one longer call, as long as the scene and `samples` stay the same.
+    /// After each pass the canvas holds the current estimate and `on_pass` is
+    /// called with it and the number of passes so far. Adaptive sampling
+    /// isn't used.
+    pub fn render_progressive<F>(&mut self, scene: &Scene, threads: u32, depth: u32, samples: u32, budget: RenderBudget, mut on_pass: F)
+        where F: FnMut(&Canvas, u32)
+    {
+        scene.object_bvh();
+        if self.passes == 0 {
+            self.accumulation = vec![(Color::black(), 0.); self.image_data.len()];
+            self.sample_counts.fill(0);
+        }
+        let start = Instant::now();
+        let mut passes = 0;
+        while match budget {
+            RenderBudget::PASSES(n) => passes < n,
+            RenderBudget::TIME(time) => start.elapsed() < time,
+        } {
+            self.progressive_pass(scene, threads, depth, samples);
+            passes += 1;
+            on_pass(self, self.passes);
+        }
+    }
+
+    fn progressive_pass(&mut self, scene: &Scene, threads: u32, depth: u32, n: u32) {
+        let pass = self.passes;
+        let width = self.width;
+        let height = self.height;
+        self.traced_samples = vec![Vec::new(); self.image_data.len()];
+        self.trace_tiles(
+            threads,
+            |x, y| -> TracedSamples {
+                scene.sampler.pixel_samples(x, y, n, pass).iter()
+                    .map(|sample| (sample.pixel, Self::trace_sample(scene, (width, height), x, y, sample, depth)))
+                    .collect()
+            },
+            |canvas, tile, pixels| {
+                for ((x, y), samples) in tile.pixels().zip(pixels) {
+                    let index = canvas.index(x, y);
+                    canvas.sample_counts[index] += samples.len() as u32;
+                    canvas.traced_samples[index] = samples;
+                }
+            },
+            None,
+            None,
+        );
+        for y in 0..height {
+            for x in 0..width {
+                let (color, weight) = match scene.filter {
+                    Some(filter) => self.filtered(&filter, x, y),
+                    None => self.traced_samples[self.index(x, y)].iter()
+                        .fold((Color::black(), 0.), |(sum, count), &(_, color)| (sum + color, count + 1.)),
+                };
+                let index = self.index(x, y);
+                let (sum, total) = self.accumulation[index];
+                self.accumulation[index] = (sum + color, total + weight);
+                if total + weight > 0. {
+                    self.set_pixel(x, y, (sum + color) * (1. / (total + weight)));
+                }
+            }
+        }
+        self.traced_samples = Vec::new();
+        self.passes += 1;
+    }
+
+    /// Color seen through `sample` of pixel `(x, y)` of a canvas of `size`.
+    fn trace_sample(scene: &Scene, size: (u32, u32), x: u32, y: u32, sample: &Sample, depth: u32) -> Color {
+        let width = size.0 as f64;
+        let height = size.1 as f64;
+        let camera = scene.camera();
+        let ray = camera.lens_ray(
+            (x as f64 + sample.pixel.0) / width - 0.5,
+            0.5 - (y as f64 + sample.pixel.1) / height,
+            width / height,
+            sample.lens,
+        );
+        scene.trace_ray(&ray, AIR_REFRACTION_INDEX, camera.near_t()..=f64::INFINITY, depth)
+    }
+
+    /// Samples of pixel `(x, y)` of a canvas of `size`: `n * n` of them, and
+    /// more if adaptive sampling asks for them.
+    fn trace_pixel(scene: &Scene, size: (u32, u32), x: u32, y: u32, n: u32, depth: u32) -> TracedSamples {
+        let trace = |sample: &Sample| Self::trace_sample(scene, size, x, y, sample, depth);
+        let mut samples = scene.sampler.pixel_samples(x, y, n, 0);
+        let mut colors: Vec<Color> = samples.iter().map(trace).collect();
+        if let Some(adaptive) = scene.adaptive_sampling {
+            let mut pass = 1;
+            while adaptive.refine(&colors) {
+                let remaining = adaptive.max_samples as usize - colors.len();
+                let more: Vec<Sample> = scene.sampler.pixel_samples(x, y, n, pass).into_iter().take(remaining).collect();
+                colors.extend(more.iter().map(trace));
+                samples.extend(more);
+                pass += 1;
+            }
+        }
+        samples.iter().map(|sample| sample.pixel).zip(colors).collect()
+    }
+
+    fn store_pixel(&mut self, scene: &Scene, x: u32, y: u32, samples: TracedSamples) {
+        let index = self.index(x, y);
+        self.sample_counts[index] = samples.len() as u32;
+        let color = samples.iter().fold(Color::black(), |sum, &(_, c)| sum + c);
+        self.set_pixel(x, y, color * (1. / samples.len() as f64));
+        if scene.filter.is_some() {
+            self.traced_samples[index] = samples;
+        }
+    }
+
+    pub fn draw_line(&mut self, p0: Point, p1: Point, color: Color) {
+        Raster::draw_line(self, p0, p1, color);
+    }
+
+    pub fn draw_wireframe_triangle(&mut self, p0: Point, p1: Point, p2: Point, color: Color) {
+        Raster::draw_wireframe_triangle(self, p0, p1, p2, color);
+    }
+
+    pub fn draw_shaded_triangle(&mut self, p0: Point, p1: Point, p2: Point, compute_color: &dyn Fn(Point, [f64; 3]) -> Color) {
+        Raster::draw_shaded_triangle(self, p0, p1, p2, compute_color);
+    }
+
+    pub fn rasterize(&mut self, scene: &Scene, shading: Shading, wireframe: bool) {
+        let projection = scene.get_projection_matrix(self.width, self.height);
+        let camera = scene.get_camera_matrix();
+
+        let clipping_planes = scene.get_clipping_planes(self.width, self.height);
+        for instance in scene.instances.iter() {
+            if let Some(instance) = Self::project_instance(scene, instance, &camera, &projection, &clipping_planes) {
+                for t in instance.triangles.iter() {
+                    self.render_triangle(t, &instance, &camera, scene, shading, wireframe);
+                }
+            }
+        }
+    }
+
+    /// Same as `rasterize`, on `threads` threads. Instances are transformed
+    /// and clipped in parallel, then each thread draws whole tiles, going
+    /// through the triangles that touch a tile in the same order as `rasterize`.
+    pub fn rasterize_mth(&mut self, scene: &Scene, threads: u32, shading: Shading, wireframe: bool) {
+        let projection = scene.get_projection_matrix(self.width, self.height);
+        let camera = scene.get_camera_matrix();
+
+        let clipping_planes = scene.get_clipping_planes(self.width, self.height);
+        let instances: Vec<ProjectedInstance> = parallel_map(&scene.instances, threads, |instance| {
+            Self::project_instance(scene, instance, &camera, &projection, &clipping_planes)
+        }).into_iter().flatten().collect();
+
+        let tiles = self.tiles();
+        let mut bins = vec![Vec::new(); tiles.len()];
+        for (i, instance) in instances.iter().enumerate() {
+            for (j, triangle) in instance.triangles.iter().enumerate() {
+                for tile in self.tiles_touched(triangle.indices.map(|k| instance.projected[k])) {
+                    bins[tile].push((i, j));
+                }
+            }
+        }
+        let canvas = &*self;
+        let rasters = parallel_map(&tiles.into_iter().zip(bins).collect::<Vec<_>>(), threads, |(tile, bin)| {
+            let mut raster = TileRaster::new(canvas, *tile);
+            for &(i, j) in bin {
+                raster.render_triangle(&instances[i].triangles[j], &instances[i], &camera, scene, shading, wireframe);
+            }
+            raster
+        });
+        for raster in rasters {
+            self.copy_tile(&raster);
+        }
+    }
+
+    /// Indices into `tiles` of the tiles a triangle with these projected
+    /// corners may draw on, wireframe included.
+    fn tiles_touched(&self, corners: [Point; 3]) -> Vec<usize> {
+        let tiles_x = self.width.div_ceil(TILE_SIZE);
+        let tiles_y = self.height.div_ceil(TILE_SIZE);
+        let xs = corners.map(|p| p.x());
+        let ys = corners.map(|p| p.y());
+        // rounding along the edges can land up to a pixel outside the corners
+        let left = xs[0].min(xs[1]).min(xs[2]).floor() - 1. + (self.width / 2) as f64;
+        let right = xs[0].max(xs[1]).max(xs[2]).ceil() + 1. + (self.width / 2) as f64;
+        let top = (self.height / 2) as f64 - ys[0].max(ys[1]).max(ys[2]).ceil() - 1.;
+        let bottom = (self.height / 2) as f64 - ys[0].min(ys[1]).min(ys[2]).floor() + 1.;
+        if !(left.is_finite() && right.is_finite() && top.is_finite() && bottom.is_finite()) {
+            return (0..(tiles_x * tiles_y) as usize).collect();
+        }
+        if right < 0. || bottom < 0. || left >= self.width as f64 || top >= self.height as f64 {
+            return Vec::new();
+        }
+        let column = |x: f64| x.clamp(0., (self.width - 1) as f64) as u32 / TILE_SIZE;
+        let row = |y: f64| y.clamp(0., (self.height - 1) as f64) as u32 / TILE_SIZE;
+        (row(top)..=row(bottom))
+            .flat_map(|ty| (column(left)..=column(right)).map(move |tx| (ty * tiles_x + tx) as usize))
+            .collect()
+    }
+
+    fn copy_tile(&mut self, raster: &TileRaster) {
+        let tile = raster.tile;
+        for y in 0..tile.height {
+            let from = (y * tile.width) as usize..((y + 1) * tile.width) as usize;
+            let to = self.index(tile.x, tile.y + y)..self.index(tile.x, tile.y + y) + tile.width as usize;
+            self.image_data[to.clone()].copy_from_slice(&raster.image_data[from.clone()]);
+            self.depth_buffer[to].copy_from_slice(&raster.depth_buffer[from]);
+        }
+        self.packed.take();
+    }
+
+    /// An instance transformed into camera space and clipped, or `None` if
+    /// nothing of it is in view.
+    fn project_instance(
+        scene: &Scene, instance: &SceneModelInstance,
+        camera: &Matrix, projection: &Matrix, clipping_planes: &Vec<Plane>,
+    ) -> Option<ProjectedInstance> {
+        let model = scene.models.iter().find(|&model| model.name == instance.model_name).expect("no model found for instance");
+        let transform: Matrix = *camera * instance.transform;
+        let normal_transform = transform.normal_matrix();
+        let vertices: Vec<Point> = model.vertices.iter()
+            .map(|&v| transform * v)
+            .collect();
+        let mut triangles = model.triangles.clone();
+        for tr in triangles.iter_mut() {
+            // a singular transform flattens the model, which is still drawn
+            // but shaded with the normals of its flattened faces
+            tr.normals = normal_transform.and_then(|normal_transform| tr.normals.map(|ns| ns.map(|n| {
+                let n = normal_transform * n;
+                n / n.length()
+            })));
+        }
+        let model = SceneModel::new(model.name.clone(), vertices, triangles);
+        let (vertices, triangles) = Self::clip_model(clipping_planes, model)?;
+        let projected = vertices.iter()
+            .map(|v| {
+                let mut p = *projection * *v;
+                p.set_z(v.z());
+                p
+            })
+            .collect();
+        Some(ProjectedInstance { vertices, projected, triangles })
+    }
+
+    fn clip_model(clipping_planes: &Vec<Plane>, mut model: SceneModel) -> Option<(Vec<Point>, Vec<SceneModelTriangle>)> {
+        let bounding_sphere = model.get_bounding_sphere();
+        let mut intersection_planes = Vec::new();
+        for plane in clipping_planes {
+            let d = plane.signed_distance(&bounding_sphere.center);
+            if d < -bounding_sphere.radius {
+                return None;
+            }
+            if d < bounding_sphere.radius {
+                intersection_planes.push(plane);
             }
         }
-        self.set_pixel(x, y, color * (1. / (n * n) as f64));
+        let SceneModel { mut vertices, mut triangles, ..} = model;
+        if intersection_planes.is_empty() {
+            return Some((vertices, triangles));
+        }
+        for plane in intersection_planes {
+            let trs = triangles;
+            triangles = Vec::new();
+            for SceneModelTriangle { indices, mut normals, color, specular } in trs {
+                let mut distance_id_pairs = indices.iter()
+                    .map(|&vid| (plane.signed_distance(&vertices[vid]), vid))
+                    .collect::<Vec<_>>();
+
+                loop {
+                    if distance_id_pairs[0].0 < distance_id_pairs[1].0 || distance_id_pairs[0].0 < distance_id_pairs[2].0 {
+                        distance_id_pairs.rotate_left(1);
+                        normals = normals.map(|ns| [ns[1], ns[2], ns[0]]);
+                    } else {
+                        break;
+                    }
+                }
+
+                if distance_id_pairs[0].0 <= 0. {
+                    continue
+                } else if distance_id_pairs[1].0 >= 0. && distance_id_pairs[2].0 >= 0. {
+                    triangles.push(SceneModelTriangle {
+                        indices: [distance_id_pairs[0].1, distance_id_pairs[1].1, distance_id_pairs[2].1],
+                        normals,
+                        color,
+                        specular
+                    });
+                } else if distance_id_pairs[1].0 <= 0. && distance_id_pairs[2].0 <= 0. {
+                    let (tb, b) = plane.intersection(&vertices[distance_id_pairs[0].1], &vertices[distance_id_pairs[1].1]).unwrap();
+                    let (tc, c) = plane.intersection(&vertices[distance_id_pairs[0].1], &vertices[distance_id_pairs[2].1]).unwrap();
+                    let l = vertices.len();
+                    vertices.push(b);
+                    vertices.push(c);
+                    triangles.push(SceneModelTriangle {
+                        indices: [distance_id_pairs[0].1, l, l + 1],
+                        normals: normals.map(|ns|
+                            [
+                                ns[0],
+                                ns[0] + (ns[1] - ns[0]) * tb,
+                                ns[0] + (ns[2] - ns[0]) * tc,
+                            ]
+                        ),
+                        color,
+                        specular,
+                    });
+                } else {
+                    if distance_id_pairs[2].0 > 0. {
+                        distance_id_pairs.rotate_right(1);
+                        normals = normals.map(|ns| [ns[2], ns[0], ns[1]]);
+                    }
+                    let (ta, a) = plane.intersection(&vertices[distance_id_pairs[0].1], &vertices[distance_id_pairs[2].1]).unwrap();
+                    let (tb, b) = plane.intersection(&vertices[distance_id_pairs[1].1], &vertices[distance_id_pairs[2].1]).unwrap();
+                    let l = vertices.len();
+                    vertices.push(a);
+                    vertices.push(b);
+                    triangles.push(SceneModelTriangle {
+                        indices: [distance_id_pairs[0].1, distance_id_pairs[1].1, l],
+                        normals: normals.map(|ns|
+                            [
+                                ns[0],
+                                ns[1],
+                                ns[0] + (ns[2] - ns[0]) * ta,
+                            ]
+                        ),
+                        color,
+                        specular,
+                    });
+                    triangles.push(SceneModelTriangle {
+                        indices: [distance_id_pairs[1].1, l + 1, l],
+                        normals: normals.map(|ns|
+                            [
+                                ns[1],
+                                ns[1] + (ns[2] - ns[1]) * tb,
+                                ns[0] + (ns[2] - ns[0]) * ta,
+                            ]
+                        ),
+                        color,
+                        specular,
+                    });
+                }
+            }
+        }
+        if triangles.is_empty() {
+            None
+        } else {
+            Some((vertices, triangles))
+        }
     }
+}
+
+/// Something the rasterizer draws on, addressed with the origin at the
+/// center of the canvas and y pointing up.
+trait Raster {
+    fn canvas_size(&self) -> (u32, u32);
+
+    /// Smallest and largest x, then y, that land on the raster.
+    fn bounds(&self) -> ((i32, i32), (i32, i32));
+
+    fn update_depth_buffer(&mut self, x: i32, y: i32, iz: f64) -> bool;
+
+    fn put_pixel(&mut self, x: i32, y: i32, color: Color);
 
-    pub fn draw_line(&mut self, mut p0: Point, mut p1: Point, color: Color) {
+    fn draw_line(&mut self, mut p0: Point, mut p1: Point, color: Color) {
         let x_ys: Box<dyn Iterator<Item = (i32, i32)>> = if (p0.x() - p1.x()).abs() > (p0.y() - p1.y()).abs() {
             if p0.x() > p1.x() {
                 mem::swap(&mut p0, &mut p1);
@@ -160,7 +747,7 @@ impl Canvas {
             let x0 = p0.x().round() as i32;
             let x1 = p1.x().round() as i32;
             Box::new(
-                (x0..=x1).into_iter()
+                (x0..=x1)
                     .zip(interpolate(x0, p0.y(), x1, p1.y()).into_iter().map(|y| y as i32))
             )
         } else {
@@ -171,7 +758,7 @@ impl Canvas {
             let y1 = p1.y().round() as i32;
             Box::new(
                 interpolate(y0, p0.x(), y1, p1.x()).into_iter().map(|x| x as i32)
-                    .zip((y0..=y1).into_iter())
+                    .zip(y0..=y1)
             )
         };
         for (x, y) in x_ys {
@@ -179,16 +766,16 @@ impl Canvas {
         }
     }
 
-    pub fn draw_wireframe_triangle(&mut self, p0: Point, p1: Point, p2: Point, color: Color) {
+    fn draw_wireframe_triangle(&mut self, p0: Point, p1: Point, p2: Point, color: Color) {
         self.draw_line(p0, p1, color);
         self.draw_line(p1, p2, color);
         self.draw_line(p2, p0, color);
     }
 
-    pub fn draw_shaded_triangle(&mut self, mut p0: Point, mut p1: Point, mut p2: Point, compute_color: &dyn Fn(Point, [f64; 3]) -> Color) {
-        let mut v0 = Point::from((1., 0., 0.));
-        let mut v1 = Point::from((0., 1., 0.));
-        let mut v2 = Point::from((0., 0., 1.));
+    fn draw_shaded_triangle(&mut self, mut p0: Point, mut p1: Point, mut p2: Point, compute_color: &dyn Fn(Point, [f64; 3]) -> Color) {
+        let mut v0 = Vector::from((1., 0., 0.));
+        let mut v1 = Vector::from((0., 1., 0.));
+        let mut v2 = Vector::from((0., 0., 1.));
         if p1.y() < p0.y() {
             mem::swap(&mut p1, &mut p0);
             mem::swap(&mut v1, &mut v0);
@@ -237,15 +824,17 @@ impl Canvas {
                 (&x012, &x02, &iz012, &iz02, &v012, &v02)
             }
         };
-        for y in y0..=y2 {
+        let ((x_min, x_max), (y_min, y_max)) = self.bounds();
+        for y in y0.max(y_min)..=y2.min(y_max) {
             let i = (y - y0) as usize;
             let l = x_left[i].round() as i32;
             let r = x_right[i].round() as i32;
-            let izs = interpolate(l, iz_left[i], r, iz_right[i]);
-            let vs = interpolate(l, v_left[i], r, v_right[i]);
-            for x in l..=r {
-                let iz = izs[(x - l) as usize];
-                let v = vs[(x - l) as usize];
+            let (from, to) = (l.max(x_min), r.min(x_max));
+            let izs = interpolate_between(l, iz_left[i], r, iz_right[i], from, to);
+            let vs = interpolate_between(l, v_left[i], r, v_right[i], from, to);
+            for x in from..=to {
+                let iz = izs[(x - from) as usize];
+                let v = vs[(x - from) as usize];
                 if self.update_depth_buffer(x, y, iz) {
                     self.put_pixel(x, y, compute_color((x as f64, y as f64, iz).into(), [v.x(), v.y(), v.z()]));
                 }
@@ -255,46 +844,52 @@ impl Canvas {
 
     fn render_triangle(
         &mut self,
-        triangle: &SceneModelTriangle, projected: &Vec<Point>, model_vertices: &Vec<Point>,
+        triangle: &SceneModelTriangle, instance: &ProjectedInstance,
         camera_transform: &Matrix, scene: &Scene,
         shading: Shading,
         wireframe: bool,
     ) {
+        let projected = &instance.projected;
+        let model_vertices = &instance.vertices;
         let [i, j, k] = triangle.indices;
         let normal = Triangle::new(model_vertices[i], model_vertices[j], model_vertices[k]).normal;
-        let center = (model_vertices[i] + model_vertices[j] + model_vertices[k]) / 3.;
+        let center = model_vertices[i] + ((model_vertices[j] - model_vertices[i]) + (model_vertices[k] - model_vertices[i])) / 3.;
         let specular = 50;
-        if normal.dot(&center) < 0. {
+        // the camera sits at the origin of camera space
+        let view = |point: &Point| Point::origin() - *point;
+        if normal.dot(&view(&center)) > 0. {
             let normals = triangle.normals.unwrap_or([normal; 3]);
 
             let color_fn: Box<dyn Fn(Point, [f64; 3]) -> Color>  = match shading {
                 Shading::FLAT => {
                     let intensity = scene.lights.iter().map(|light|
-                        light.intensity_after(camera_transform, &center, &normal, &-center, specular)).sum();
+                        light.intensity_after(camera_transform, &center, &normal, &view(&center), specular)).sum();
                     let color = triangle.color * intensity;
                     Box::new(move |_point: Point, _mix: [f64; 3]| color)
                 },
                 Shading::GOURAUD => {
                     let ia: f64 = scene.lights.iter().map(|light|
-                        light.intensity_after(camera_transform, &model_vertices[i], &normals[0], &-model_vertices[i], specular)).sum();
+                        light.intensity_after(camera_transform, &model_vertices[i], &normals[0], &view(&model_vertices[i]), specular)).sum();
                     let ib: f64 = scene.lights.iter().map(|light|
-                        light.intensity_after(camera_transform, &model_vertices[j], &normals[1], &-model_vertices[j], specular)).sum();
+                        light.intensity_after(camera_transform, &model_vertices[j], &normals[1], &view(&model_vertices[j]), specular)).sum();
                     let ic: f64 = scene.lights.iter().map(|light|
-                        light.intensity_after(camera_transform, &model_vertices[k], &normals[2], &-model_vertices[k], specular)).sum();
+                        light.intensity_after(camera_transform, &model_vertices[k], &normals[2], &view(&model_vertices[k]), specular)).sum();
                     Box::new(move |_point: Point, mix: [f64; 3]| triangle.color * (ia * mix[0] + ib * mix[1] + ic * mix[2]))
                 },
                 Shading::PHONG => {
-                    let canvas_width = self.width as f64;
-                    let canvas_height = self.height as f64;
+                    let (width, height) = self.canvas_size();
+                    let canvas_width = width as f64;
+                    let canvas_height = height as f64;
+                    let (viewport_width, viewport_height) = scene.viewport_size(width, height);
                     Box::new(
                         move |point: Point, mix: [f64; 3]| {
                             let z = 1. / point.z();
-                            let x = point.x() * scene.viewport_width / canvas_width / scene.camera_distance * z;
-                            let y = point.y() * scene.viewport_height / canvas_height / scene.camera_distance * z;
-                            let point = (x, y, z).into();
+                            let x = point.x() * viewport_width / canvas_width / scene.camera.distance * z;
+                            let y = point.y() * viewport_height / canvas_height / scene.camera.distance * z;
+                            let point: Point = (x, y, z).into();
                             let normal = normals[0] * mix[0] + normals[1] * mix[1] + normals[2] * mix[2];
                             let intensity = scene.lights.iter().map(|light|
-                                light.intensity_after(camera_transform, &point, &normal, &-point.vector(), specular)).sum();
+                                light.intensity_after(camera_transform, &point, &normal, &view(&point), specular)).sum();
                             triangle.color * intensity
                         }
                     )
@@ -312,158 +907,139 @@ impl Canvas {
             }
         }
     }
+}
 
-    pub fn rasterize(&mut self, scene: &Scene, shading: Shading, wireframe: bool) {
-        let projection = scene.get_projection_matrix(self.width, self.height);
-        let camera = scene.get_camera_matrix();
+impl Raster for Canvas {
+    fn canvas_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
 
-        let clipping_planes = scene.get_clipping_planes();
-        for instance in scene.instances.iter() {
-            let model = scene.models.iter().find(|&model| model.name == instance.model_name).expect("no model found for instance");
-            let transform: Matrix = camera * instance.transform;
-            let vertices: Vec<Point> = model.vertices.iter()
-                .map(|&v| transform.dot(&v.into()))
-                .collect();
-            let mut triangles = model.triangles.clone();
-            for tr in triangles.iter_mut() {
-                tr.normals = tr.normals.map(|ns| {
-                    [
-                        transform.dot(&ns[0]),
-                        transform.dot(&ns[1]),
-                        transform.dot(&ns[2]),
-                    ]
-                });
-            }
-            let model = SceneModel::new(model.name.clone(), vertices, triangles);
-            if let Some((vertices, triangles)) = Self::clip_model(&clipping_planes, model) {
-                let projected = vertices.iter()
-                    .map(|v| {
-                        let mut p = projection.dot(v).canonical();
-                        p.set_z(v.z());
-                        p
-                    })
-                    .collect::<Vec<_>>();
-                for t in triangles.iter() {
-                    self.render_triangle(t, &projected, &vertices, &camera, scene, shading, wireframe);
-                }
-            }
+    fn bounds(&self) -> ((i32, i32), (i32, i32)) {
+        let (width, height) = (self.width as i32, self.height as i32);
+        ((-(width / 2), width - 1 - width / 2), (height / 2 - (height - 1), height / 2))
+    }
+
+    fn update_depth_buffer(&mut self, x: i32, y: i32, iz: f64) -> bool {
+        let x = x + self.width as i32 / 2;
+        let y = self.height as i32 / 2 - y;
+        if x < 0 || x >= self.width as i32 || y < 0 || y >= self.height as i32 {
+            return false;
+        }
+        let index = self.index(x as u32, y as u32);
+        if self.depth_buffer[index] < iz {
+            self.depth_buffer[index] = iz;
+            true
+        } else {
+            false
         }
     }
 
-    fn clip_model(clipping_planes: &Vec<Plane>, mut model: SceneModel) -> Option<(Vec<Point>, Vec<SceneModelTriangle>)> {
-        let bounding_sphere = model.get_bounding_sphere();
-        let mut intersection_planes = Vec::new();
-        for plane in clipping_planes {
-            let d = plane.signed_distance(&bounding_sphere.center);
-            if d < -bounding_sphere.radius {
-                return None;
-            }
-            if d < bounding_sphere.radius {
-                intersection_planes.push(plane);
-            }
+    fn put_pixel(&mut self, x: i32, y: i32, color: Color) {
+        Canvas::put_pixel(self, x, y, color);
+    }
+}
+
+/// An instance's triangles after clipping, with their vertices in camera
+/// space and projected onto the canvas.
+struct ProjectedInstance {
+    vertices: Vec<Point>,
+    projected: Vec<Point>,
+    triangles: Vec<SceneModelTriangle>,
+}
+
+/// One tile of a canvas, rasterized apart from the rest of it.
+struct TileRaster {
+    tile: Tile,
+    canvas_size: (u32, u32),
+    image_data: Vec<Color>,
+    depth_buffer: Vec<f64>,
+}
+
+impl TileRaster {
+    fn new(canvas: &Canvas, tile: Tile) -> Self {
+        let mut image_data = Vec::with_capacity((tile.width * tile.height) as usize);
+        let mut depth_buffer = Vec::with_capacity((tile.width * tile.height) as usize);
+        for y in tile.y..tile.y + tile.height {
+            let row = canvas.index(tile.x, y)..canvas.index(tile.x, y) + tile.width as usize;
+            image_data.extend_from_slice(&canvas.image_data[row.clone()]);
+            depth_buffer.extend_from_slice(&canvas.depth_buffer[row]);
         }
-        let SceneModel { mut vertices, mut triangles, ..} = model;
-        if intersection_planes.len() == 0 {
-            return Some((vertices, triangles));
+        TileRaster {
+            tile,
+            canvas_size: (canvas.width, canvas.height),
+            image_data,
+            depth_buffer,
         }
-        for plane in intersection_planes {
-            let trs = triangles;
-            triangles = Vec::new();
-            for SceneModelTriangle { indices, mut normals, color, specular } in trs {
-                let mut distance_id_pairs = indices.iter()
-                    .map(|&vid| (plane.signed_distance(&vertices[vid]), vid))
-                    .collect::<Vec<_>>();
+    }
 
-                loop {
-                    if distance_id_pairs[0].0 < distance_id_pairs[1].0 || distance_id_pairs[0].0 < distance_id_pairs[2].0 {
-                        distance_id_pairs.rotate_left(1);
-                        normals = normals.map(|ns| [ns[1], ns[2], ns[0]]);
-                    } else {
-                        break;
-                    }
-                }
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let x = x + (self.canvas_size.0 / 2) as i32 - self.tile.x as i32;
+        let y = (self.canvas_size.1 / 2) as i32 - y - self.tile.y as i32;
+        if x < 0 || x >= self.tile.width as i32 || y < 0 || y >= self.tile.height as i32 {
+            return None;
+        }
+        Some(y as usize * self.tile.width as usize + x as usize)
+    }
+}
 
-                if distance_id_pairs[0].0 <= 0. {
-                    continue
-                } else if distance_id_pairs[1].0 >= 0. && distance_id_pairs[2].0 >= 0. {
-                    triangles.push(SceneModelTriangle {
-                        indices: [distance_id_pairs[0].1, distance_id_pairs[1].1, distance_id_pairs[2].1],
-                        normals,
-                        color,
-                        specular
-                    });
-                } else if distance_id_pairs[1].0 <= 0. && distance_id_pairs[2].0 <= 0. {
-                    let (tb, b) = plane.intersection(&vertices[distance_id_pairs[0].1], &vertices[distance_id_pairs[1].1]).unwrap();
-                    let (tc, c) = plane.intersection(&vertices[distance_id_pairs[0].1], &vertices[distance_id_pairs[2].1]).unwrap();
-                    let l = vertices.len();
-                    vertices.push(b);
-                    vertices.push(c);
-                    triangles.push(SceneModelTriangle {
-                        indices: [distance_id_pairs[0].1, l, l + 1],
-                        normals: normals.map(|ns|
-                            [
-                                ns[0],
-                                ns[0] + (ns[1] - ns[0]) * tb,
-                                ns[0] + (ns[2] - ns[0]) * tc,
-                            ]
-                        ),
-                        color,
-                        specular,
-                    });
-                } else {
-                    if distance_id_pairs[2].0 > 0. {
-                        distance_id_pairs.rotate_right(1);
-                        normals = normals.map(|ns| [ns[2], ns[0], ns[1]]);
-                    }
-                    let (ta, a) = plane.intersection(&vertices[distance_id_pairs[0].1], &vertices[distance_id_pairs[2].1]).unwrap();
-                    let (tb, b) = plane.intersection(&vertices[distance_id_pairs[1].1], &vertices[distance_id_pairs[2].1]).unwrap();
-                    let l = vertices.len();
-                    vertices.push(a);
-                    vertices.push(b);
-                    triangles.push(SceneModelTriangle {
-                        indices: [distance_id_pairs[0].1, distance_id_pairs[1].1, l],
-                        normals: normals.map(|ns|
-                            [
-                                ns[0],
-                                ns[1],
-                                ns[0] + (ns[2] - ns[0]) * ta,
-                            ]
-                        ),
-                        color,
-                        specular,
-                    });
-                    triangles.push(SceneModelTriangle {
-                        indices: [distance_id_pairs[1].1, l + 1, l],
-                        normals: normals.map(|ns|
-                            [
-                                ns[1],
-                                ns[1] + (ns[2] - ns[1]) * tb,
-                                ns[0] + (ns[2] - ns[0]) * ta,
-                            ]
-                        ),
-                        color,
-                        specular,
-                    });
-                }
-            }
+impl Raster for TileRaster {
+    fn canvas_size(&self) -> (u32, u32) {
+        self.canvas_size
+    }
+
+    fn bounds(&self) -> ((i32, i32), (i32, i32)) {
+        let (x, y) = (self.tile.x as i32 - (self.canvas_size.0 / 2) as i32, (self.canvas_size.1 / 2) as i32 - self.tile.y as i32);
+        ((x, x + self.tile.width as i32 - 1), (y - (self.tile.height as i32 - 1), y))
+    }
+
+    fn update_depth_buffer(&mut self, x: i32, y: i32, iz: f64) -> bool {
+        match self.index(x, y) {
+            Some(index) if self.depth_buffer[index] < iz => {
+                self.depth_buffer[index] = iz;
+                true
+            },
+            _ => false,
         }
-        if triangles.len() == 0 {
-            None
-        } else {
-            Some((vertices, triangles))
+    }
+
+    fn put_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if let Some(index) = self.index(x, y) {
+            self.image_data[index] = color;
         }
     }
 }
 
+/// `f` applied to each of `items` on `threads` threads, in order.
+fn parallel_map<T, R, F>(items: &[T], threads: u32, f: F) -> Vec<R>
+    where T: Sync, R: Send, F: Fn(&T) -> R + Sync
+{
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads.max(1)).map(|_| s.spawn(|| {
+            let mut done = Vec::new();
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else { break };
+                done.push((i, f(item)));
+            }
+            done
+        })).collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    });
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
 pub struct Scene {
-    viewport_width: f64,
-    viewport_height: f64,
     background: Color,
     objects: Vec<Box<dyn SceneObject + Sync>>,
+    object_bvh: OnceLock<Bvh>,
     lights: Vec<Box<dyn LightObject + Sync>>,
-    camera_position: Point,
-    camera_rotation: Matrix,
-    camera_distance: f64,
+    camera: PerspectiveCamera,
+    ray_camera: Option<Box<dyn Camera + Sync>>,
+    sampler: Box<dyn Sampler + Sync>,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    filter: Option<PixelFilter>,
     models: Vec<SceneModel>,
     instances: Vec<SceneModelInstance>,
 }
@@ -475,27 +1051,87 @@ impl Scene {
         background: Color,
     ) -> Self {
         Scene {
-            viewport_width,
-            viewport_height,
             background,
             objects: Vec::new(),
+            object_bvh: OnceLock::new(),
             lights: Vec::new(),
-            camera_position: (0., 0., 0.).into(),
-            camera_rotation: Matrix::identity(),
-            camera_distance: 1.,
+            camera: PerspectiveCamera::new(viewport_width, viewport_height),
+            ray_camera: None,
+            sampler: Box::new(RegularSampler),
+            adaptive_sampling: None,
+            filter: None,
             models: Vec::new(),
             instances: Vec::new(),
         }
     }
 
-    pub fn set_camera(&mut self, position: Point, rotation: Matrix, distance: f64) {
-        self.camera_position = position;
-        self.camera_rotation = rotation;
-        self.camera_distance = distance;
+    pub fn set_camera<R: Into<Matrix>>(&mut self, position: Point, rotation: R, distance: f64) {
+        self.camera.position = position;
+        self.camera.rotation = rotation.into();
+        self.camera.distance = distance;
+        self.camera.field_of_view = None;
+    }
+
+    /// Puts the camera at `eye` looking towards `target`, with `up` pointing
+    /// up on screen as far as possible. `fov` is the vertical field of view
+    /// in degrees, and the horizontal one follows the canvas's aspect ratio.
+    /// The camera is focused on `target`, keeping its aperture. Panics if
+    /// `eye` and `target` coincide.
+    pub fn look_at(&mut self, eye: Point, target: Point, up: Vector, fov: f64) {
+        let camera = self.camera;
+        self.camera = PerspectiveCamera::look_at(eye, target, up, fov);
+        self.camera.viewport_width = camera.viewport_width;
+        self.camera.viewport_height = camera.viewport_height;
+        self.camera.aperture = camera.aperture;
+    }
+
+    /// Gives the perspective camera a lens `aperture` units across, focused
+    /// at `focus_distance` along the view direction. Each ray-tracing sample
+    /// then leaves from a different point of the lens, blurring whatever is
+    /// out of focus. An aperture of zero turns depth of field off.
+    pub fn set_depth_of_field(&mut self, aperture: f64, focus_distance: f64) {
+        self.camera.aperture = aperture;
+        self.camera.focus_distance = focus_distance;
+    }
+
+    /// Camera for ray tracing in place of the perspective camera set up by
+    /// `set_camera` or `look_at`. The rasterizer always uses the latter.
+    pub fn set_ray_camera(&mut self, camera: Box<dyn Camera + Sync>) {
+        self.ray_camera = Some(camera);
+    }
+
+    /// Where ray tracing samples each pixel. Defaults to a regular grid.
+    pub fn set_sampler(&mut self, sampler: Box<dyn Sampler + Sync>) {
+        self.sampler = sampler;
+    }
+
+    /// Turns on adaptive supersampling, where the `samples` given to
+    /// `Canvas::render` set the first pass only. `None` turns it off.
+    pub fn set_adaptive_sampling(&mut self, adaptive: Option<AdaptiveSampling>) {
+        self.adaptive_sampling = adaptive;
+    }
+
+    /// Reconstruction filter for ray tracing. `None`, the default, averages
+    /// the samples of each pixel on their own.
+    pub fn set_filter(&mut self, filter: Option<PixelFilter>) {
+        self.filter = filter;
+    }
+
+    pub fn camera(&self) -> &dyn Camera {
+        match &self.ray_camera {
+            Some(camera) => camera.as_ref(),
+            None => &self.camera,
+        }
+    }
+
+    /// Size of the perspective camera's viewport for a canvas of the given size.
+    pub fn viewport_size(&self, canvas_width: u32, canvas_height: u32) -> (f64, f64) {
+        self.camera.viewport_size(canvas_width as f64 / canvas_height as f64)
     }
 
     pub fn add_object(&mut self, object: Box<dyn SceneObject + Sync>) {
         self.objects.push(object);
+        self.object_bvh.take();
     }
 
     pub fn add_light(&mut self, light: Box<dyn LightObject + Sync>) {
@@ -511,29 +1147,22 @@ impl Scene {
     }
 
     pub fn canvas_to_viewport(&self, x: i32, y: i32, width: u32, height: u32) -> Ray {
-        Ray {
-            origin: self.camera_position,
-            direction: self.camera_rotation.dot(&(
-                x as f64 / width as f64 * self.viewport_width,
-                y as f64 / height as f64 * self.viewport_height,
-                self.camera_distance,
-                0.,
-            ).into())
-        }
+        self.camera().ray(x as f64 / width as f64, y as f64 / height as f64, width as f64 / height as f64)
     }
 
     pub fn get_camera_matrix(&self) -> Matrix {
-        let pos = self.camera_position;
+        let pos = self.camera.position;
         Matrix::compose(vec![
-            self.camera_rotation.transposed(),
+            self.camera.rotation.transposed(),
             Matrix::translation(-pos.x(), -pos.y(), -pos.z()),
         ])
     }
 
     pub fn get_projection_matrix(&self, canvas_width: u32, canvas_height: u32) -> Matrix {
+        let (viewport_width, viewport_height) = self.viewport_size(canvas_width, canvas_height);
         Matrix::compose(vec![
             {
-                let d = self.camera_distance;
+                let d = self.camera.distance;
                 let mut mat = [[0.; 4]; 4];
                 mat[0][0] = d;
                 mat[1][1] = d;
@@ -543,8 +1172,8 @@ impl Scene {
             },
             {
                 let mut mat = [[0.; 4]; 4];
-                mat[0][0] = canvas_width as f64 / self.viewport_width;
-                mat[1][1] = canvas_height as f64 / self.viewport_height;
+                mat[0][0] = canvas_width as f64 / viewport_width;
+                mat[1][1] = canvas_height as f64 / viewport_height;
                 mat[2][2] = 1.;
                 mat[3][3] = 1.;
                 Matrix::new(mat)
@@ -552,14 +1181,15 @@ impl Scene {
         ])
     }
 
-    pub fn get_clipping_planes(&self) -> Vec<Plane> {
+    pub fn get_clipping_planes(&self, canvas_width: u32, canvas_height: u32) -> Vec<Plane> {
+        let (viewport_width, viewport_height) = self.viewport_size(canvas_width, canvas_height);
         let camera_pos = Point::from((0., 0., 0.));
-        let top_left = Point::from((-self.viewport_width / 2., self.viewport_height / 2., self.camera_distance));
-        let top_right = Point::from((self.viewport_width / 2., self.viewport_height / 2., self.camera_distance));
-        let bottom_right = Point::from((self.viewport_width / 2., -self.viewport_height / 2., self.camera_distance));
-        let bottom_left = Point::from((-self.viewport_width / 2., -self.viewport_height / 2., self.camera_distance));
+        let top_left = Point::from((-viewport_width / 2., viewport_height / 2., self.camera.distance));
+        let top_right = Point::from((viewport_width / 2., viewport_height / 2., self.camera.distance));
+        let bottom_right = Point::from((viewport_width / 2., -viewport_height / 2., self.camera.distance));
+        let bottom_left = Point::from((-viewport_width / 2., -viewport_height / 2., self.camera.distance));
         vec![
-            Plane { normal: (0., 0., 1., 0.).into(), d: -self.camera_distance },
+            Plane { normal: (0., 0., 1.).into(), d: -self.camera.distance },
             Plane::from_points(camera_pos, top_left, bottom_left),
             Plane::from_points(camera_pos, top_right, top_left),
             Plane::from_points(camera_pos, bottom_right, top_right),
@@ -571,39 +1201,33 @@ impl Scene {
         self.lights.iter().map(|light| light.intensity_from(self, point, normal, view, specular)).sum()
     }
 
-    pub fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
-        let mut result: Option<HitTestResult> = None;
-
-        for object in self.objects.iter() {
-            if let Some(r) = object.hit_test(ray, &t_range) {
-                if result.is_none() {
-                    result = Some(r)
-                } else if r.t < result.unwrap().t {
-                    result = Some(r)
-                }
-            }
-        }
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        self.objects.iter()
+            .filter_map(|object| object.bounding_box())
+            .filter(|b| !b.is_empty())
+            .reduce(|acc, b| acc.union(&b))
+    }
 
-        result
+    fn object_bvh(&self) -> &Bvh {
+        self.object_bvh.get_or_init(|| Bvh::new(self.objects.iter().map(|object| object.bounding_box()).collect()))
     }
 
-    fn container_hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
-        let mut result: Option<HitTestResult> = None;
+    pub fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        self.object_bvh().closest_hit(ray, t_range, |i| {
+            self.objects[i].hit_test(ray, t_range).map(|r| (r.t, r))
+        })
+    }
 
-        for object in self.objects.iter() {
-            if let Some(r) = object.hit_test(ray, &t_range) {
-                if r.normal.dot(&ray.direction) <= 0. {
-                    continue;
-                }
-                if result.is_none() {
-                    result = Some(r)
-                } else if r.t < result.unwrap().t {
-                    result = Some(r)
-                }
-            }
-        }
+    pub fn occlusion_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> bool {
+        self.object_bvh().any_hit(ray, t_range, |i| self.objects[i].occlusion_test(ray, t_range))
+    }
 
-        result
+    fn container_hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        self.object_bvh().closest_hit(ray, t_range, |i| {
+            self.objects[i].hit_test(ray, t_range)
+                .filter(|r| r.normal.dot(&ray.direction) > 0.)
+                .map(|r| (r.t, r))
+        })
     }
 
     pub fn trace_ray(&self, ray: &Ray, refraction_index: f64, t_range: RangeInclusive<f64>, depth: u32) -> Color {
@@ -623,30 +1247,216 @@ impl Scene {
                     local_color * (1. - hit.material.reflective) + reflected_color * hit.material.reflective
                 }
             };
-            if depth == 0 || hit.material.transparency.is_none() {
-                opaque_color
-            } else {
-                let in_vector = ray.direction / ray.direction.length();
-                let going_outside_object = hit.normal.dot(&in_vector) > 0.;
-                let new_refraction_index = if going_outside_object {
-                    self.container_hit_test(&Ray { origin: hit.point, direction: ray.direction }, &(EPS..=f64::INFINITY))
-                    .map(|container_hit| container_hit.material.transparency)
-                    .flatten().unwrap_or(AIR_REFRACTION_INDEX)
-                } else {
-                    hit.material.transparency.unwrap()
-                };
-                let normal = if going_outside_object { -hit.normal } else { hit.normal };
-                let cos = normal.dot(&in_vector);
-                let k = refraction_index / new_refraction_index;
-                let d = 1. - k * k * (1. - cos * cos);
-                if d < 0. {
-                    opaque_color
-                } else {
-                    let refraction_vector: Vector = (in_vector - normal * cos) * k - normal * d.sqrt();
-                    let p = cos.abs().sqrt();
-                    opaque_color * (1. - p) + self.trace_ray(&Ray { origin: hit.point, direction: refraction_vector }, new_refraction_index, EPS..=f64::INFINITY, depth - 1) * p
-                }
+            match hit.material.transparency {
+                Some(transparency) if depth > 0 => {
+                    let in_vector = ray.direction / ray.direction.length();
+                    let going_outside_object = hit.normal.dot(&in_vector) > 0.;
+                    let new_refraction_index = if going_outside_object {
+                        self.container_hit_test(&Ray { origin: hit.point, direction: ray.direction }, &(EPS..=f64::INFINITY))
+                        .and_then(|container_hit| container_hit.material.transparency)
+                        .unwrap_or(AIR_REFRACTION_INDEX)
+                    } else {
+                        transparency
+                    };
+                    let normal = if going_outside_object { -hit.normal } else { hit.normal };
+                    let cos = normal.dot(&in_vector);
+                    let k = refraction_index / new_refraction_index;
+                    let d = 1. - k * k * (1. - cos * cos);
+                    if d < 0. {
+                        opaque_color
+                    } else {
+                        let refraction_vector: Vector = (in_vector - normal * cos) * k - normal * d.sqrt();
+                        let p = cos.abs().sqrt();
+                        opaque_color * (1. - p) + self.trace_ray(&Ray { origin: hit.point, direction: refraction_vector }, new_refraction_index, EPS..=f64::INFINITY, depth - 1) * p
+                    }
+                },
+                _ => opaque_color,
             }
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Material = Material {
+        color: Color::red(),
+        specular: -1,
+        reflective: 0.,
+        transparency: None,
+    };
+
+    fn lit_scene() -> Scene {
+        let mut scene = Scene::new(1., 1., Color::black());
+        scene.add_light(Box::new(AmbientLight { intensity: 1. }));
+        scene
+    }
+
+    fn pixels(canvas: &Canvas) -> Vec<(f32, f32, f32)> {
+        canvas.hdr_data().iter().map(|c| (c.r, c.g, c.b)).collect()
+    }
+
+    fn center_pixel(scene: &Scene, width: u32, height: u32) -> Color {
+        let mut canvas = Canvas::new(width, height, Color::black());
+        canvas.render(scene, 0, 1);
+        canvas.get_pixel(width / 2, height / 2)
+    }
+
+    #[test]
+    fn cameras_without_a_viewport_see_objects_close_by() {
+        let cameras: Vec<Box<dyn Camera + Sync>> = vec![
+            Box::new(OrthographicCamera { position: Point::origin(), rotation: Matrix::identity(), height: 2. }),
+            Box::new(FisheyeCamera { position: Point::origin(), rotation: Matrix::identity(), field_of_view: 180. }),
+            Box::new(EquirectangularCamera { position: Point::origin(), rotation: Matrix::identity() }),
+        ];
+        for camera in cameras {
+            let mut scene = lit_scene();
+            scene.add_object(Box::new(QuadObject {
+                corner: (-5., -5., 0.5).into(),
+                edge_u: (10., 0., 0.).into(),
+                edge_v: (0., 10., 0.).into(),
+                material: RED,
+            }));
+            scene.set_ray_camera(camera);
+            assert_eq!(center_pixel(&scene, 16, 8).r, 255.);
+        }
+    }
+
+    #[test]
+    fn depth_of_field_keeps_objects_nearer_than_the_focus() {
+        let mut scene = lit_scene();
+        scene.add_object(Box::new(SphereObject {
+            sphere: Sphere { center: (0., 0., 3.).into(), radius: 1. },
+            material: RED,
+        }));
+        scene.set_depth_of_field(0.1, 10.);
+        assert_eq!(center_pixel(&scene, 8, 8).r, 255.);
+    }
+
+    fn ray_traced_scene() -> Scene {
+        let mut scene = lit_scene();
+        scene.add_light(Box::new(PointLight { intensity: 0.6, position: (2., 1., 0.).into() }));
+        for (i, &(x, y, z)) in [(0., -1., 3.), (2., 0., 4.), (-2., 0., 4.), (0., -5001., 0.)].iter().enumerate() {
+            scene.add_object(Box::new(SphereObject {
+                sphere: Sphere { center: (x, y, z).into(), radius: if i == 3 { 5000. } else { 1. } },
+                material: Material {
+                    color: [Color::red(), Color::green(), Color::blue(), Color::white()][i],
+                    specular: 500,
+                    reflective: 0.3,
+                    transparency: if i == 1 { Some(1.3) } else { None },
+                },
+            }));
+        }
+        scene.set_sampler(Box::new(JitteredSampler { seed: 7 }));
+        scene.set_adaptive_sampling(Some(AdaptiveSampling { threshold: 0.1, max_samples: 16 }));
+        scene.set_filter(Some(PixelFilter { kind: FilterKind::MITCHELL, radius: 2. }));
+        scene
+    }
+
+    #[test]
+    fn render_mth_matches_render() {
+        let scene = ray_traced_scene();
+        let mut serial = Canvas::new(37, 23, Color::black());
+        serial.render(&scene, 2, 2);
+        assert!(serial.sample_counts().iter().any(|&n| n > 4), "adaptive sampling never refined");
+        for threads in [1, 2, 3, 8] {
+            let mut canvas = Canvas::new(37, 23, Color::black());
+            canvas.render_mth(&scene, threads, 2, 2);
+            assert!(pixels(&canvas) == pixels(&serial), "{} threads", threads);
+            assert_eq!(canvas.sample_counts(), serial.sample_counts());
+        }
+    }
+
+    #[test]
+    fn cancelled_render_only_filters_finished_tiles() {
+        let mut scene = Scene::new(1., 1., Color::white());
+        scene.set_filter(Some(PixelFilter { kind: FilterKind::TENT, radius: 2. }));
+        let mut canvas = Canvas::new(48, 48, Color::black());
+        let cancel = CancelToken::new();
+        let mut finished = Vec::new();
+        let mut observer = |progress: &RenderProgress| {
+            finished.push(progress.tile);
+            cancel.cancel();
+        };
+        assert!(!canvas.render_mth_with(&scene, 1, 0, 1, Some(&mut observer), Some(&cancel)));
+        assert!(finished.len() < 9);
+        for y in 0..48 {
+            for x in 0..48 {
+                let inside = finished.iter().any(|t| (t.x..t.x + t.width).contains(&x) && (t.y..t.y + t.height).contains(&y));
+                let r = canvas.get_pixel(x, y).r;
+                assert!(if inside { (r - 255.).abs() < 1e-3 } else { r == 0. }, "pixel ({}, {}) is {}", x, y, r);
+            }
+        }
+    }
+
+    #[test]
+    fn rasterize_mth_matches_rasterize() {
+        let mut scene = lit_scene();
+        scene.add_light(Box::new(PointLight { intensity: 0.6, position: (2., 1., 0.).into() }));
+        scene.add_model(SceneModel::create_sphere_model("red".into(), 12, Color::red(), 50));
+        scene.add_model(SceneModel::create_sphere_model("green".into(), 8, Color::green(), -1));
+        scene.add_instance(SceneModelInstance {
+            model_name: "red".into(),
+            transform: Matrix::translation(0., -0.5, 3.),
+        });
+        scene.add_instance(SceneModelInstance {
+            model_name: "green".into(),
+            transform: Matrix::compose(vec![Matrix::translation(0.8, 0., 3.5), Matrix::scale_xyz(1.5, 0.7, 1.)]),
+        });
+        scene.add_instance(SceneModelInstance {
+            model_name: "red".into(),
+            transform: Matrix::translation(-1.5, 0.5, 2.2),
+        });
+        for shading in [Shading::FLAT, Shading::GOURAUD, Shading::PHONG] {
+            for wireframe in [false, true] {
+                let mut serial = Canvas::new(61, 45, Color::black());
+                serial.rasterize(&scene, shading, wireframe);
+                assert!(pixels(&serial).iter().any(|&(r, _, _)| r > 0.) && pixels(&serial).iter().any(|&(_, g, _)| g > 0.));
+                for threads in [1, 2, 3, 8] {
+                    let mut canvas = Canvas::new(61, 45, Color::black());
+                    canvas.rasterize_mth(&scene, threads, shading, wireframe);
+                    assert!(pixels(&canvas) == pixels(&serial), "{:?}, wireframe {}, {} threads", shading, wireframe, threads);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flattened_instances_are_still_drawn() {
+        let mut scene = lit_scene();
+        scene.add_model(SceneModel::create_sphere_model("red".into(), 12, Color::red(), 50));
+        scene.add_instance(SceneModelInstance {
+            model_name: "red".into(),
+            transform: Matrix::compose(vec![Matrix::translation(0., 0., 3.), Matrix::scale_xyz(1., 1., 0.)]),
+        });
+        for shading in [Shading::FLAT, Shading::GOURAUD, Shading::PHONG] {
+            for wireframe in [false, true] {
+                let mut canvas = Canvas::new(31, 31, Color::black());
+                canvas.rasterize(&scene, shading, wireframe);
+                assert!(pixels(&canvas).iter().any(|&(r, _, _)| r > 0.), "{:?}, wireframe {}", shading, wireframe);
+            }
+        }
+    }
+
+    #[test]
+    fn look_at_straight_down() {
+        let mut scene = lit_scene();
+        scene.add_object(Box::new(SphereObject {
+            sphere: Sphere { center: (0., 0., 3.).into(), radius: 1. },
+            material: RED,
+        }));
+        scene.look_at((0., 10., 3.).into(), (0., 0., 3.).into(), (0., 1., 0.).into(), 30.);
+        for axis in [(1., 0., 0.), (0., 1., 0.), (0., 0., 1.)] {
+            let v = scene.camera.rotation * Vector::from(axis);
+            assert!(v.x().is_finite() && v.y().is_finite() && v.z().is_finite());
+        }
+        assert_eq!(center_pixel(&scene, 8, 8).r, 255.);
+    }
+
+    #[test]
+    #[should_panic]
+    fn look_at_needs_a_target_apart_from_the_eye() {
+        lit_scene().look_at((0., 1., 3.).into(), (0., 1., 3.).into(), (0., 1., 0.).into(), 30.);
+    }
+}
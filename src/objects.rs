@@ -1,5 +1,6 @@
 use std::ops::RangeInclusive;
-use crate::{EPS, Scene, components::*};
+use std::sync::Arc;
+use crate::{EPS, Scene, bvh::Bvh, components::*};
 
 #[derive(Clone, Copy, Debug)]
 pub struct HitTestResult {
@@ -9,8 +10,89 @@ pub struct HitTestResult {
     pub material: Material,
 }
 
+/// The part of a ray that lies inside a solid object. Normals point out of
+/// the solid, and a `None` bound means the span continues to infinity.
+#[derive(Clone, Copy, Debug)]
+pub struct HitInterval {
+    pub enter: Option<HitTestResult>,
+    pub exit: Option<HitTestResult>,
+}
+
+impl HitInterval {
+    fn enter_t(&self) -> f64 {
+        self.enter.map_or(-f64::INFINITY, |hit| hit.t)
+    }
+
+    fn exit_t(&self) -> f64 {
+        self.exit.map_or(f64::INFINITY, |hit| hit.t)
+    }
+}
+
+fn first_hit(intervals: &[HitInterval], t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+    intervals.iter()
+        .flat_map(|interval| [interval.enter, interval.exit])
+        .flatten()
+        .find(|hit| t_range.contains(&hit.t))
+}
+
+// Pairs up hits sorted by `t` as entry and exit, flipping normals that face
+// the wrong way for their role. An unpaired last hit, left by a grazing ray
+// or a crack in a mesh, is dropped rather than opening a span to infinity.
+fn intervals_from_hits(ray: &Ray, hits: Vec<HitTestResult>) -> Vec<HitInterval> {
+    let orient = |mut hit: HitTestResult, enter: bool| {
+        let f = hit.normal.dot(&ray.direction);
+        if (enter && f > 0.) || (!enter && f < 0.) {
+            hit.normal = -hit.normal;
+        }
+        hit
+    };
+    hits.chunks_exact(2)
+        .map(|pair| HitInterval {
+            enter: Some(orient(pair[0], true)),
+            exit: Some(orient(pair[1], false)),
+        })
+        .collect()
+}
+
+fn boundaries(intervals: &[HitInterval]) -> impl Iterator<Item = (f64, bool, Option<HitTestResult>)> + '_ {
+    intervals.iter()
+        .flat_map(|interval| [(interval.enter_t(), true, interval.enter), (interval.exit_t(), false, interval.exit)])
+}
+
 pub trait SceneObject {
     fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult>;
+
+    /// Whether the object blocks the ray anywhere within `t_range`.
+    /// Unlike `hit_test` it may stop at the first intersection found.
+    fn occlusion_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> bool {
+        self.hit_test(ray, t_range).is_some()
+    }
+
+    /// Every span of the whole line through `ray` that lies inside the object,
+    /// sorted by `t`. This is what `CsgObject` combines.
+    /// The default collects all hits from `hit_test` and pairs them up, which
+    /// suits closed surfaces.
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        let mut hits: Vec<HitTestResult> = Vec::new();
+        let mut t_min = -f64::INFINITY;
+        // `t` is in units of the ray direction's length, so step by EPS of
+        // distance along the ray rather than of `t`
+        let step = EPS / ray.direction.length();
+        while let Some(hit) = self.hit_test(ray, &(t_min..=f64::INFINITY)) {
+            hits.push(hit);
+            if hit.t + step == hit.t {
+                break;
+            }
+            t_min = hit.t + step;
+        }
+        intervals_from_hits(ray, hits)
+    }
+
+    /// Box enclosing every point `hit_test` can return.
+    /// `None` means the object is unbounded or its extent is unknown.
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        None
+    }
 }
 
 pub struct SphereObject {
@@ -18,23 +100,44 @@ pub struct SphereObject {
     pub material: Material,
 }
 
+fn sphere_hit(sphere: &Sphere, ray: &Ray, t: f64, material: Material) -> HitTestResult {
+    let point: Point = ray.origin + ray.direction * t;
+    let normal: Vector = point - sphere.center;
+    HitTestResult {
+        t,
+        point,
+        normal: normal / normal.length(),
+        material,
+    }
+}
+
+fn sphere_intervals(sphere: &Sphere, ray: &Ray, material: Material) -> Vec<HitInterval> {
+    sphere.compute_ray_intersection(ray).map_or(Vec::new(), |(t1, t2)| vec![
+        HitInterval {
+            enter: Some(sphere_hit(sphere, ray, t1, material)),
+            exit: Some(sphere_hit(sphere, ray, t2, material)),
+        }
+    ])
+}
+
 impl SceneObject for SphereObject {
     fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
         let (t1, t2) = self.sphere.compute_ray_intersection(ray)?;
         for &t in [t1, t2].iter() {
             if t_range.contains(&t) {
-                let point: Point = ray.origin + ray.direction * t;
-                let normal: Vector = point - self.sphere.center;
-                return Some(HitTestResult {
-                    t,
-                    point,
-                    normal: normal / normal.length(),
-                    material: self.material,
-                });
+                return Some(sphere_hit(&self.sphere, ray, t, self.material));
             }
         }
         None
     }
+
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        sphere_intervals(&self.sphere, ray, self.material)
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(self.sphere.bounding_box())
+    }
 }
 
 pub enum BooleanOperation {
@@ -43,6 +146,70 @@ pub enum BooleanOperation {
     SUBTRACTION
 }
 
+impl BooleanOperation {
+    fn contains(&self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            BooleanOperation::UNION => in_a || in_b,
+            BooleanOperation::INTERSECTION => in_a && in_b,
+            BooleanOperation::SUBTRACTION => in_a && !in_b,
+        }
+    }
+
+    /// Combines the spans two solids report along the same ray.
+    pub fn combine(&self, a: &[HitInterval], b: &[HitInterval]) -> Vec<HitInterval> {
+        let mut boundaries_a = boundaries(a).peekable();
+        let mut boundaries_b = boundaries(b).peekable();
+
+        let mut in_a = false;
+        let mut in_b = false;
+        let mut enter = None;
+        let mut intervals = Vec::new();
+        loop {
+            let from_a = match (boundaries_a.peek(), boundaries_b.peek()) {
+                (Some(x), Some(y)) => x.0 <= y.0,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let (_, entering, hit) = if from_a { boundaries_a.next() } else { boundaries_b.next() }.unwrap();
+            let was_inside = self.contains(in_a, in_b);
+            if from_a {
+                in_a = entering;
+            } else {
+                in_b = entering;
+            }
+            let inside = self.contains(in_a, in_b);
+            if inside == was_inside {
+                continue;
+            }
+            // leaving the subtracted object enters the result, so its normal has to turn around
+            let hit = hit.map(|mut hit| {
+                if inside != entering {
+                    hit.normal = -hit.normal;
+                }
+                hit
+            });
+            if inside {
+                enter = hit;
+            } else {
+                intervals.push(HitInterval { enter, exit: hit });
+            }
+        }
+        intervals
+    }
+
+    fn bounding_box(&self, box_a: Option<BoundingBox>, box_b: Option<BoundingBox>) -> Option<BoundingBox> {
+        match self {
+            BooleanOperation::UNION => Some(box_a?.union(&box_b?)),
+            BooleanOperation::INTERSECTION => match (box_a, box_b) {
+                (Some(a), Some(b)) => Some(a.intersection(&b)),
+                (a, b) => a.or(b),
+            },
+            BooleanOperation::SUBTRACTION => box_a,
+        }
+    }
+}
+
 pub struct BooleanOperationSpheresObject {
     pub sphere_a: Sphere,
     pub operation: BooleanOperation,
@@ -52,128 +219,685 @@ pub struct BooleanOperationSpheresObject {
 
 impl SceneObject for BooleanOperationSpheresObject {
     fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
-        let ta = self.sphere_a.compute_ray_intersection(ray);
-        let tb = self.sphere_b.compute_ray_intersection(ray);
-        if ta.is_none() && tb.is_none() {
+        first_hit(&self.hit_intervals(ray), t_range)
+    }
+
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        self.operation.combine(
+            &sphere_intervals(&self.sphere_a, ray, self.material),
+            &sphere_intervals(&self.sphere_b, ray, self.material),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        self.operation.bounding_box(Some(self.sphere_a.bounding_box()), Some(self.sphere_b.bounding_box()))
+    }
+}
+
+/// Boolean combination of any two objects, which may be `CsgObject`s
+/// themselves. Every surface keeps the material of the object it belongs to.
+pub struct CsgObject {
+    pub object_a: Box<dyn SceneObject + Sync>,
+    pub operation: BooleanOperation,
+    pub object_b: Box<dyn SceneObject + Sync>,
+}
+
+impl SceneObject for CsgObject {
+    fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        first_hit(&self.hit_intervals(ray), t_range)
+    }
+
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        self.operation.combine(&self.object_a.hit_intervals(ray), &self.object_b.hit_intervals(ray))
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        self.operation.bounding_box(self.object_a.bounding_box(), self.object_b.bounding_box())
+    }
+}
+
+pub struct PolyhedronObject {
+    triangles: Vec<Triangle>,
+    bvh: Bvh,
+    pub material: Material,
+}
+
+impl PolyhedronObject {
+    pub fn new(triangles: Vec<Triangle>, material: Material) -> Self {
+        let bvh = Bvh::new(triangles.iter()
+            .map(|triangle| Some(triangle.bounding_box()))
+            .collect());
+        Self {
+            triangles,
+            bvh,
+            material,
+        }
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+}
+
+impl SceneObject for PolyhedronObject {
+    fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        let (t, normal) = self.bvh.closest_hit(ray, t_range, |i| {
+            let triangle = &self.triangles[i];
+            triangle.compute_ray_intersection(ray)
+                .filter(|t| t_range.contains(t))
+                .map(|t| (t, (t, triangle.normal)))
+        })?;
+        Some(HitTestResult {
+            t,
+            point: ray.origin + ray.direction * t,
+            normal,
+            material: self.material,
+        })
+    }
+
+    fn occlusion_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> bool {
+        self.bvh.any_hit(ray, t_range, |i| {
+            self.triangles[i].compute_ray_intersection(ray).is_some_and(|t| t_range.contains(&t))
+        })
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(self.triangles.iter().fold(BoundingBox::empty(), |acc, triangle| acc.union(&triangle.bounding_box())))
+    }
+}
+
+/// Infinite plane. The solid side, as seen by `CsgObject`, is the half-space
+/// behind `plane.normal`.
+pub struct PlaneObject {
+    pub plane: Plane,
+    pub material: Material,
+}
+
+impl PlaneObject {
+    fn hit_at(&self, ray: &Ray, t: f64) -> HitTestResult {
+        HitTestResult {
+            t,
+            point: ray.origin + ray.direction * t,
+            normal: self.plane.normal / self.plane.normal.length(),
+            material: self.material,
+        }
+    }
+}
+
+impl SceneObject for PlaneObject {
+    fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        self.plane.compute_ray_intersection(ray)
+            .filter(|t| t_range.contains(t))
+            .map(|t| self.hit_at(ray, t))
+    }
+
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        match self.plane.compute_ray_intersection(ray) {
+            Some(t) if self.plane.normal.dot(&ray.direction) < 0. => vec![HitInterval { enter: Some(self.hit_at(ray, t)), exit: None }],
+            Some(t) => vec![HitInterval { enter: None, exit: Some(self.hit_at(ray, t)) }],
+            None if self.plane.signed_distance(&ray.origin) < 0. => vec![HitInterval { enter: None, exit: None }],
+            None => Vec::new(),
+        }
+    }
+}
+
+// Open surfaces have no inside, so their normal faces whichever side the ray comes from.
+fn open_surface_hit(ray: &Ray, t: f64, normal: &Vector, material: Material) -> HitTestResult {
+    let normal: Vector = *normal / normal.length();
+    HitTestResult {
+        t,
+        point: ray.origin + ray.direction * t,
+        normal: if normal.dot(&ray.direction) > 0. { -normal } else { normal },
+        material,
+    }
+}
+
+/// Parallelogram spanned by `edge_u` and `edge_v` from `corner`.
+/// It has no inside, so it adds nothing to a `CsgObject`.
+pub struct QuadObject {
+    pub corner: Point,
+    pub edge_u: Vector,
+    pub edge_v: Vector,
+    pub material: Material,
+}
+
+impl SceneObject for QuadObject {
+    fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        let normal = self.edge_u.cross(&self.edge_v);
+        let t = Plane::from_point_normal(self.corner, normal).compute_ray_intersection(ray)
+            .filter(|t| t_range.contains(t))?;
+        let p: Vector = ray.origin + ray.direction * t - self.corner;
+        let w = normal / normal.dot(&normal);
+        let u = w.dot(&p.cross(&self.edge_v));
+        let v = w.dot(&self.edge_u.cross(&p));
+        if !(0. ..=1.).contains(&u) || !(0. ..=1.).contains(&v) {
             return None;
         }
-        let range_a = ta.unwrap_or((-f64::INFINITY, -f64::INFINITY));
-        let range_b = tb.unwrap_or((-f64::INFINITY, -f64::INFINITY));
-        let has_no_intersection = range_b.0 > range_a.1 || range_a.0 > range_b.1;
-        let break_points: Vec<(f64, &Sphere)> = match self.operation {
-            BooleanOperation::UNION => {
-                if has_no_intersection {
-                    if range_b.0 > range_a.1 {
-                        vec![
-                            (range_a.0, &self.sphere_a),
-                            (range_a.1, &self.sphere_a),
-                            (range_b.0, &self.sphere_b),
-                            (range_b.1, &self.sphere_b),
-                        ]
-                    } else {
-                        vec![
-                            (range_b.0, &self.sphere_b),
-                            (range_b.1, &self.sphere_b),
-                            (range_a.0, &self.sphere_a),
-                            (range_a.1, &self.sphere_a),
-                        ]
-                    }
-
-                } else {
-                    vec![
-                        if range_a.0 < range_b.0 { (range_a.0, &self.sphere_a) } else { (range_b.0, &self.sphere_b) },
-                        if range_a.1 < range_b.1 { (range_b.1, &self.sphere_b) } else { (range_a.1, &self.sphere_a) },
-                    ]
-                }
-            },
-            BooleanOperation::INTERSECTION => {
-                if has_no_intersection {
-                    Vec::new()
-                } else {
-                    vec![
-                        if range_a.0 < range_b.0 { (range_b.0, &self.sphere_b) } else { (range_a.0, &self.sphere_a) },
-                        if range_a.1 < range_b.1 { (range_a.1, &self.sphere_a) } else { (range_b.1, &self.sphere_b) },
-                    ]
-                }
-            },
-            BooleanOperation::SUBTRACTION => {
-                if has_no_intersection {
-                    vec![
-                        (range_a.0, &self.sphere_a),
-                        (range_a.1, &self.sphere_a),
-                    ]
-                } else if range_b.0 > range_a.0 {
-                    if range_b.1 >= range_a.1 {
-                        vec![
-                            (range_a.0, &self.sphere_a),
-                            (range_b.0, &self.sphere_b),
-                        ]
-                    } else {
-                        vec![
-                            (range_a.0, &self.sphere_a),
-                            (range_b.0, &self.sphere_b),
-                            (range_b.1, &self.sphere_a),
-                            (range_a.1, &self.sphere_a),
-                        ]
-                    }
-                } else if range_b.1 < range_a.1 {
-                    vec![
-                        (range_b.1, &self.sphere_b),
-                        (range_a.1, &self.sphere_a),
-                    ]
-                } else {
-                    Vec::new()
+        Some(open_surface_hit(ray, t, &normal, self.material))
+    }
+
+    fn hit_intervals(&self, _ray: &Ray) -> Vec<HitInterval> {
+        Vec::new()
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(BoundingBox::from_points(&[
+            self.corner,
+            self.corner + self.edge_u,
+            self.corner + self.edge_v,
+            self.corner + self.edge_u + self.edge_v,
+        ]))
+    }
+}
+
+fn disk_bounding_box(center: &Point, normal: &Vector, radius: f64) -> BoundingBox {
+    let n: Vector = *normal / normal.length();
+    let extent = |c: f64| radius * (1. - c * c).max(0.).sqrt();
+    let r: Vector = (extent(n.x()), extent(n.y()), extent(n.z())).into();
+    BoundingBox::new(*center - r, *center + r)
+}
+
+/// Flat disk facing `normal`.
+/// It has no inside, so it adds nothing to a `CsgObject`.
+pub struct DiskObject {
+    pub center: Point,
+    pub normal: Vector,
+    pub radius: f64,
+    pub material: Material,
+}
+
+impl SceneObject for DiskObject {
+    fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        let t = Plane::from_point_normal(self.center, self.normal).compute_ray_intersection(ray)
+            .filter(|t| t_range.contains(t))?;
+        let p: Vector = ray.origin + ray.direction * t - self.center;
+        if p.dot(&p) > self.radius * self.radius {
+            return None;
+        }
+        Some(open_surface_hit(ray, t, &self.normal, self.material))
+    }
+
+    fn hit_intervals(&self, _ray: &Ray) -> Vec<HitInterval> {
+        Vec::new()
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(disk_bounding_box(&self.center, &self.normal, self.radius))
+    }
+}
+
+/// Box between `min` and `max`, turned about its center by `orientation`
+/// when one is given. `orientation` has to be a rotation.
+pub struct BoxObject {
+    pub min: Point,
+    pub max: Point,
+    pub orientation: Option<Matrix>,
+    pub material: Material,
+}
+
+impl BoxObject {
+    // Slab test in the box's own frame, returning `t` and the outward normal
+    // of the faces where the ray enters and leaves.
+    fn compute_ray_intersection(&self, ray: &Ray) -> Option<[(f64, Vector); 2]> {
+        let center = self.min.midpoint(&self.max);
+        let to_local = |v: Vector| self.orientation.map_or(v, |m| m.transposed() * v);
+        let to_world = |v: Vector| self.orientation.map_or(v, |m| m * v);
+        let origin = to_local(ray.origin - center);
+        let direction = to_local(ray.direction);
+        let half = self.max - center;
+        let origin = [origin.x(), origin.y(), origin.z()];
+        let direction = [direction.x(), direction.y(), direction.z()];
+        let half = [half.x(), half.y(), half.z()];
+
+        let mut near = (-f64::INFINITY, 0, 0.);
+        let mut far = (f64::INFINITY, 0, 0.);
+        for axis in 0..3 {
+            if direction[axis] == 0. {
+                if origin[axis].abs() > half[axis] {
+                    return None;
                 }
-            },
+                continue;
+            }
+            let sign = direction[axis].signum();
+            let t1 = (-sign * half[axis] - origin[axis]) / direction[axis];
+            let t2 = (sign * half[axis] - origin[axis]) / direction[axis];
+            if t1 > near.0 {
+                near = (t1, axis, -sign);
+            }
+            if t2 < far.0 {
+                far = (t2, axis, sign);
+            }
+        }
+        if near.0 > far.0 {
+            return None;
+        }
+        let normal = |axis: usize, sign: f64| {
+            let mut n = [0.; 3];
+            n[axis] = sign;
+            to_world((n[0], n[1], n[2]).into())
         };
+        Some([(near.0, normal(near.1, near.2)), (far.0, normal(far.1, far.2))])
+    }
 
-        for (i, b) in break_points.into_iter().enumerate() {
-            if t_range.contains(&b.0) {
-                let point = ray.origin + ray.direction * b.0;
-                let mut normal: Vector = point - b.1.center;
-                let flip = {
-                    let f = normal.dot(&ray.direction);
-                    if i % 2 == 1 { f < 0. } else { f > 0. }
-                };
-                if flip {
-                    normal = -normal;
-                }
-                return Some(HitTestResult {
-                    t: b.0,
-                    point,
-                    normal: normal / normal.length(),
-                    material: self.material,
-                })
+    fn hit_at(&self, ray: &Ray, (t, normal): (f64, Vector)) -> HitTestResult {
+        HitTestResult {
+            t,
+            point: ray.origin + ray.direction * t,
+            normal,
+            material: self.material,
+        }
+    }
+}
+
+impl SceneObject for BoxObject {
+    fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        let hits = self.compute_ray_intersection(ray)?;
+        hits.iter()
+            .find(|hit| t_range.contains(&hit.0))
+            .map(|&hit| self.hit_at(ray, hit))
+    }
+
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        self.compute_ray_intersection(ray).map_or(Vec::new(), |[enter, exit]| vec![
+            HitInterval {
+                enter: Some(self.hit_at(ray, enter)),
+                exit: Some(self.hit_at(ray, exit)),
+            }
+        ])
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let bounds = BoundingBox::new(self.min, self.max);
+        Some(match self.orientation {
+            Some(orientation) => {
+                let center = bounds.center();
+                bounds.transformed(&Matrix::compose(vec![
+                    Matrix::translation(center.x(), center.y(), center.z()),
+                    orientation,
+                    Matrix::translation(-center.x(), -center.y(), -center.z()),
+                ]))
+            },
+            None => bounds,
+        })
+    }
+}
+
+// Where a ray crosses the surface of a solid, with the outward normal there.
+// `side` is false on flat end caps.
+#[derive(Clone, Copy)]
+struct Crossing {
+    t: f64,
+    normal: Vector,
+    side: bool,
+}
+
+// Splits the ray into the parts along and across the unit `axis`, relative to `base`.
+fn axis_frame(ray: &Ray, base: &Point, axis: &Vector) -> (f64, f64, Vector, Vector) {
+    let co: Vector = ray.origin - *base;
+    let o_along = co.dot(axis);
+    let d_along = ray.direction.dot(axis);
+    (o_along, d_along, co - *axis * o_along, ray.direction - *axis * d_along)
+}
+
+// Range of `t` where `a t^2 + b t + c <= 0` overlaps the slab `s1..=s2`.
+// The solids using it are convex, so at most one piece of the quadric's
+// inside can reach into the slab. Returns the span and whether each end
+// lies on the quadric rather than on the slab.
+fn quadric_span(a: f64, b: f64, c: f64, (s1, s2): (f64, f64)) -> Option<((f64, bool), (f64, bool))> {
+    let pieces = if a == 0. {
+        if b > 0. {
+            vec![(-f64::INFINITY, -c / b)]
+        } else if b < 0. {
+            vec![(-c / b, f64::INFINITY)]
+        } else if c <= 0. {
+            vec![(-f64::INFINITY, f64::INFINITY)]
+        } else {
+            Vec::new()
+        }
+    } else {
+        let discriminant = b * b - 4. * a * c;
+        if discriminant < 0. {
+            if a > 0. { Vec::new() } else { vec![(-f64::INFINITY, f64::INFINITY)] }
+        } else {
+            let t1 = (-b - discriminant.sqrt()) / (2. * a);
+            let t2 = (-b + discriminant.sqrt()) / (2. * a);
+            let (t1, t2) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+            if a > 0. {
+                vec![(t1, t2)]
+            } else {
+                vec![(-f64::INFINITY, t1), (t2, f64::INFINITY)]
             }
         }
+    };
+    pieces.into_iter()
+        .map(|(lo, hi)| ((lo.max(s1), lo > s1), (hi.min(s2), hi < s2)))
+        .find(|(enter, exit)| enter.0 <= exit.0)
+}
 
-        None
+// Range of `t` where the ray is between the planes through `base` and
+// `base + axis * height` that are perpendicular to the unit `axis`.
+fn slab_span(o_along: f64, d_along: f64, height: f64) -> Option<(f64, f64)> {
+    if d_along == 0. {
+        if (0. ..=height).contains(&o_along) { Some((-f64::INFINITY, f64::INFINITY)) } else { None }
+    } else {
+        let t1 = -o_along / d_along;
+        let t2 = (height - o_along) / d_along;
+        Some(if t1 < t2 { (t1, t2) } else { (t2, t1) })
     }
 }
 
-pub struct PolyhedronObject {
-    pub triangles: Vec<Triangle>,
+// Cylinder or cone around the unit `axis`, whose radius shrinks by `slope`
+// per unit of height from `radius` at `base`.
+fn tapered_span(ray: &Ray, base: &Point, axis: &Vector, height: f64, radius: f64, slope: f64) -> Option<[Crossing; 2]> {
+    let (o_along, d_along, o_across, d_across) = axis_frame(ray, base, axis);
+    let slab = slab_span(o_along, d_along, height)?;
+    // distance from the axis squared minus the radius squared at that height
+    let e = radius - slope * o_along;
+    let a = d_across.dot(&d_across) - slope * slope * d_along * d_along;
+    let b = 2. * (o_across.dot(&d_across) + slope * e * d_along);
+    let c = o_across.dot(&o_across) - e * e;
+    let ((t1, side1), (t2, side2)) = quadric_span(a, b, c, slab)?;
+    let crossing = |t: f64, side: bool, entering: bool| {
+        let normal = if side {
+            let across: Vector = o_across + d_across * t;
+            let n: Vector = across + *axis * (slope * (radius - slope * (o_along + d_along * t)));
+            n / n.length()
+        } else if (d_along > 0.) == entering {
+            -*axis
+        } else {
+            *axis
+        };
+        Crossing { t, normal, side }
+    };
+    Some([crossing(t1, side1, true), crossing(t2, side2, false)])
+}
+
+fn solid_hit_test(span: Option<[Crossing; 2]>, open: bool, ray: &Ray, t_range: &RangeInclusive<f64>, material: Material) -> Option<HitTestResult> {
+    span?.iter()
+        .filter(|crossing| !open || crossing.side)
+        .find(|crossing| t_range.contains(&crossing.t))
+        .map(|crossing| if open {
+            open_surface_hit(ray, crossing.t, &crossing.normal, material)
+        } else {
+            HitTestResult {
+                t: crossing.t,
+                point: ray.origin + ray.direction * crossing.t,
+                normal: crossing.normal,
+                material,
+            }
+        })
+}
+
+fn solid_intervals(span: Option<[Crossing; 2]>, open: bool, ray: &Ray, material: Material) -> Vec<HitInterval> {
+    match span {
+        Some([enter, exit]) if !open => {
+            let hit = |crossing: Crossing| HitTestResult {
+                t: crossing.t,
+                point: ray.origin + ray.direction * crossing.t,
+                normal: crossing.normal,
+                material,
+            };
+            vec![HitInterval { enter: Some(hit(enter)), exit: Some(hit(exit)) }]
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Cylinder of `radius` around the segment from `base` to `base + axis`.
+/// Without `caps` both ends are open, and it has no inside for `CsgObject`.
+pub struct CylinderObject {
+    pub base: Point,
+    pub axis: Vector,
+    pub radius: f64,
+    pub caps: bool,
     pub material: Material,
 }
 
-impl SceneObject for PolyhedronObject {
+impl CylinderObject {
+    fn compute_ray_intersection(&self, ray: &Ray) -> Option<[Crossing; 2]> {
+        let height = self.axis.length();
+        tapered_span(ray, &self.base, &(self.axis / height), height, self.radius, 0.)
+    }
+}
+
+impl SceneObject for CylinderObject {
     fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
-        let mut hits = self.triangles.iter()
-            .flat_map(|triangle| triangle.compute_ray_intersection(ray).map(|t| (t, triangle.normal)))
-            .collect::<Vec<_>>();
-        hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
-        for hit in hits {
-            if t_range.contains(&hit.0) {
-                let point: Point = ray.origin + ray.direction * hit.0;
-                return Some(HitTestResult{
-                    t: hit.0,
-                    point,
-                    normal: hit.1,
-                    material: self.material,
-                });
-            }
+        solid_hit_test(self.compute_ray_intersection(ray), !self.caps, ray, t_range, self.material)
+    }
+
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        solid_intervals(self.compute_ray_intersection(ray), !self.caps, ray, self.material)
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let top = self.base + self.axis;
+        Some(disk_bounding_box(&self.base, &self.axis, self.radius).union(&disk_bounding_box(&top, &self.axis, self.radius)))
+    }
+}
+
+/// Cone with a base of `radius` at `base` and its apex at `base + axis`.
+/// Without `caps` the base is open, and it has no inside for `CsgObject`.
+pub struct ConeObject {
+    pub base: Point,
+    pub axis: Vector,
+    pub radius: f64,
+    pub caps: bool,
+    pub material: Material,
+}
+
+impl ConeObject {
+    fn compute_ray_intersection(&self, ray: &Ray) -> Option<[Crossing; 2]> {
+        let height = self.axis.length();
+        tapered_span(ray, &self.base, &(self.axis / height), height, self.radius, self.radius / height)
+    }
+}
+
+impl SceneObject for ConeObject {
+    fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        solid_hit_test(self.compute_ray_intersection(ray), !self.caps, ray, t_range, self.material)
+    }
+
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        solid_intervals(self.compute_ray_intersection(ray), !self.caps, ray, self.material)
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let apex = self.base + self.axis;
+        Some(disk_bounding_box(&self.base, &self.axis, self.radius).union(&BoundingBox::new(apex, apex)))
+    }
+}
+
+/// Cylinder of `radius` around the segment from `base` to `base + axis`,
+/// closed off by half spheres at both ends.
+pub struct CapsuleObject {
+    pub base: Point,
+    pub axis: Vector,
+    pub radius: f64,
+    pub material: Material,
+}
+
+impl CapsuleObject {
+    // The capsule is convex, so its span runs from the first entry into any
+    // of its parts to the last exit.
+    fn compute_ray_intersection(&self, ray: &Ray) -> Option<[Crossing; 2]> {
+        let height = self.axis.length();
+        let axis = self.axis / height;
+        let ends = [self.base, self.base + self.axis];
+        let sphere_spans = ends.iter().filter_map(|&center| {
+            let sphere = Sphere { center, radius: self.radius };
+            let (t1, t2) = sphere.compute_ray_intersection(ray)?;
+            let crossing = |t: f64| {
+                let normal: Vector = ray.origin + ray.direction * t - center;
+                Crossing { t, normal: normal / normal.length(), side: true }
+            };
+            Some([crossing(t1), crossing(t2)])
+        });
+        tapered_span(ray, &self.base, &axis, height, self.radius, 0.).into_iter()
+            .chain(sphere_spans)
+            .reduce(|[enter, exit], [e, x]| [
+                if e.t < enter.t { e } else { enter },
+                if x.t > exit.t { x } else { exit },
+            ])
+    }
+}
+
+impl SceneObject for CapsuleObject {
+    fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        solid_hit_test(self.compute_ray_intersection(ray), false, ray, t_range, self.material)
+    }
+
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        solid_intervals(self.compute_ray_intersection(ray), false, ray, self.material)
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let top = self.base + self.axis;
+        Some(Sphere { center: self.base, radius: self.radius }.bounding_box()
+            .union(&Sphere { center: top, radius: self.radius }.bounding_box()))
+    }
+}
+
+/// Ring torus around `center`, with the tube of `minor_radius` following a
+/// circle of `major_radius` in the plane perpendicular to `axis`.
+pub struct TorusObject {
+    pub center: Point,
+    pub axis: Vector,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+}
+
+impl TorusObject {
+    // Ascending `t` where the ray crosses the surface.
+    fn compute_ray_intersection(&self, ray: &Ray) -> Vec<f64> {
+        let axis = self.axis / self.axis.length();
+        let scale = ray.direction.length();
+        let d = ray.direction / scale;
+        // solving from the point of the ray closest to the center keeps the
+        // coefficients small for distant origins
+        let shift = -(ray.origin - self.center).dot(&d);
+        let o: Vector = ray.origin - self.center + d * shift;
+        let r2 = self.major_radius * self.major_radius;
+        let m = o.dot(&o);
+        let n = o.dot(&d);
+        let k = m + r2 - self.minor_radius * self.minor_radius;
+        let o_along = o.dot(&axis);
+        let d_along = d.dot(&axis);
+        solve_quartic(
+            1.,
+            4. * n,
+            4. * n * n + 2. * k - 4. * r2 * (1. - d_along * d_along),
+            4. * n * k - 8. * r2 * (n - o_along * d_along),
+            k * k - 4. * r2 * (m - o_along * o_along),
+        ).into_iter()
+            .map(|s| (s + shift) / scale)
+            .collect()
+    }
+
+    fn hit_at(&self, ray: &Ray, t: f64) -> HitTestResult {
+        let axis = self.axis / self.axis.length();
+        let point = ray.origin + ray.direction * t;
+        let q: Vector = point - self.center;
+        let r2 = self.major_radius * self.major_radius;
+        let normal: Vector = q * (q.dot(&q) - r2 - self.minor_radius * self.minor_radius) + axis * (2. * r2 * q.dot(&axis));
+        HitTestResult {
+            t,
+            point,
+            normal: normal / normal.length(),
+            material: self.material,
         }
-        None
+    }
+}
+
+impl SceneObject for TorusObject {
+    fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        self.compute_ray_intersection(ray).into_iter()
+            .find(|t| t_range.contains(t))
+            .map(|t| self.hit_at(ray, t))
+    }
+
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        self.compute_ray_intersection(ray).chunks_exact(2)
+            .map(|pair| HitInterval {
+                enter: Some(self.hit_at(ray, pair[0])),
+                exit: Some(self.hit_at(ray, pair[1])),
+            })
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let ring = disk_bounding_box(&self.center, &self.axis, self.major_radius);
+        Some(ring.expanded(self.minor_radius))
+    }
+}
+
+/// Places `object` in the scene through `transform`, which maps object space
+/// to world space. The same object can be shared by many instances.
+pub struct TransformedObject {
+    object: Arc<dyn SceneObject + Send + Sync>,
+    transform: Matrix,
+    inverse: Matrix,
+    normal_matrix: Matrix,
+}
+
+impl TransformedObject {
+    /// `None` when `transform` cannot be inverted.
+    pub fn new(object: Arc<dyn SceneObject + Send + Sync>, transform: Matrix) -> Option<Self> {
+        Some(Self {
+            object,
+            transform,
+            inverse: transform.inverse()?,
+            normal_matrix: transform.normal_matrix()?,
+        })
+    }
+
+    pub fn object(&self) -> &Arc<dyn SceneObject + Send + Sync> {
+        &self.object
+    }
+
+    pub fn transform(&self) -> &Matrix {
+        &self.transform
+    }
+
+    // The direction is not normalized, so `t` means the same in both spaces.
+    fn to_object_space(&self, ray: &Ray) -> Ray {
+        Ray {
+            origin: self.inverse * ray.origin,
+            direction: self.inverse * ray.direction,
+        }
+    }
+
+    fn to_world_space(&self, ray: &Ray, mut hit: HitTestResult) -> HitTestResult {
+        let normal = self.normal_matrix * hit.normal;
+        hit.point = ray.origin + ray.direction * hit.t;
+        hit.normal = normal / normal.length();
+        hit
+    }
+}
+
+impl SceneObject for TransformedObject {
+    fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+        self.object.hit_test(&self.to_object_space(ray), t_range)
+            .map(|hit| self.to_world_space(ray, hit))
+    }
+
+    fn occlusion_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> bool {
+        self.object.occlusion_test(&self.to_object_space(ray), t_range)
+    }
+
+    fn hit_intervals(&self, ray: &Ray) -> Vec<HitInterval> {
+        self.object.hit_intervals(&self.to_object_space(ray)).into_iter()
+            .map(|interval| HitInterval {
+                enter: interval.enter.map(|hit| self.to_world_space(ray, hit)),
+                exit: interval.exit.map(|hit| self.to_world_space(ray, hit)),
+            })
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        self.object.bounding_box()
+            .filter(|b| !b.is_empty())
+            .map(|b| b.transformed(&self.transform))
     }
 }
 
@@ -212,7 +936,7 @@ pub struct PointLight {
 impl LightObject for PointLight {
     fn intensity_from(&self, scene: &Scene, point: &Point, normal: &Vector, view: &Vector, specular: i32) -> f64 {
         let light: Vector = self.position - *point;
-        if scene.hit_test(&Ray { origin: *point, direction: light }, &(EPS..=1.0)).is_some() {
+        if scene.occlusion_test(&Ray { origin: *point, direction: light }, &(EPS..=1.0)) {
             0.
         } else {
             self.intensity * compute_light_factor(normal, &light, view, specular)
@@ -220,7 +944,7 @@ impl LightObject for PointLight {
     }
 
     fn intensity_after(&self, transform: &Matrix, point: &Point, normal: &Vector, view: &Vector, specular: i32) -> f64 {
-        let light: Vector = transform.dot(&self.position) - *point;
+        let light: Vector = *transform * self.position - *point;
         self.intensity * compute_light_factor(normal, &light, view, specular)
     }
 }
@@ -232,7 +956,7 @@ pub struct DirectionalLight {
 
 impl LightObject for DirectionalLight {
     fn intensity_from(&self, scene: &Scene, point: &Point, normal: &Vector, view: &Vector, specular: i32) -> f64 {
-        if scene.hit_test(&Ray { origin: *point, direction: self.direction }, &(EPS..=f64::INFINITY)).is_some() {
+        if scene.occlusion_test(&Ray { origin: *point, direction: self.direction }, &(EPS..=f64::INFINITY)) {
             0.
         } else {
             self.intensity * compute_light_factor(normal, &self.direction, view, specular)
@@ -240,7 +964,7 @@ impl LightObject for DirectionalLight {
     }
 
     fn intensity_after(&self, transform: &Matrix, _point: &Point, normal: &Vector, view: &Vector, specular: i32) -> f64 {
-        let light = transform.dot(&self.direction);
+        let light = *transform * self.direction;
         self.intensity * compute_light_factor(normal, &light, view, specular)
     }
 }
@@ -291,33 +1015,34 @@ impl SceneModel {
         let step_h = (360. / s as f64).to_radians();
         let rxz_y_lookup = {
             let step_v = (180. / (divides + 1) as f64).to_radians();
-            (1..=divides).into_iter().map(|d| (d as f64 * step_v).sin_cos()).collect::<Vec<_>>()
+            (1..=divides).map(|d| (d as f64 * step_v).sin_cos()).collect::<Vec<_>>()
         };
         let mut lngs = Vec::new();
         for k in 0..s {
             let mut lng = Vec::new();
             let (sin, cos) = (step_h * k as f64).sin_cos();
             lng.push(0);
-            for i in 0..divides {
+            for &(rxz, y) in rxz_y_lookup.iter() {
                 lng.push(vertices.len());
-                vertices.push(Point::from((rxz_y_lookup[i].0 * cos, rxz_y_lookup[i].1, rxz_y_lookup[i].0 * sin)));
+                vertices.push(Point::from((rxz * cos, y, rxz * sin)));
             }
             lng.push(1);
             lngs.push(lng);
         }
+        let origin = Point::origin();
         for k in 0..s {
             let lng1 = &lngs[k];
             let lng2 = &lngs[(k + 1) % s];
             for i in 0..divides {
                 triangles.push(SceneModelTriangle {
                     indices: [lng1[i], lng2[i+1], lng1[i+1]],
-                    normals: Some([vertices[lng1[i]].vector(), vertices[lng2[i+1]].vector(), vertices[lng1[i+1]].vector()]),
+                    normals: Some([vertices[lng1[i]] - origin, vertices[lng2[i+1]] - origin, vertices[lng1[i+1]] - origin]),
                     color,
                     specular,
                 });
                 triangles.push(SceneModelTriangle {
                     indices: [lng1[i+1], lng2[i+1], lng2[i+2]],
-                    normals: Some([vertices[lng1[i+1]].vector(), vertices[lng2[i+1]].vector(), vertices[lng2[i+2]].vector()]),
+                    normals: Some([vertices[lng1[i+1]] - origin, vertices[lng2[i+1]] - origin, vertices[lng2[i+2]] - origin]),
                     color,
                     specular,
                 });
@@ -336,8 +1061,9 @@ impl SceneModel {
         if let Some(sphere) = self.bounding_sphere {
             sphere
         } else {
-            let center: Point = self.vertices.iter().map(|&v| Point::from(v)).fold(Point::from((0., 0., 0., 0.)), |acc, v| acc + v) / self.vertices.len() as f64;
-            let radius = self.vertices.iter().map(|&v| (Point::from(v) - center).length()).max_by(|x, y| x.partial_cmp(y).unwrap()).unwrap_or(0.);
+            let origin = Point::origin();
+            let center: Point = origin + self.vertices.iter().fold(Vector::from((0., 0., 0.)), |acc, &v| acc + (v - origin)) / self.vertices.len() as f64;
+            let radius = self.vertices.iter().map(|&v| (v - center).length()).max_by(|x, y| x.partial_cmp(y).unwrap()).unwrap_or(0.);
             let sphere = Sphere {
                 center,
                 radius,
@@ -351,4 +1077,212 @@ impl SceneModel {
 pub struct SceneModelInstance {
     pub model_name: String,
     pub transform: Matrix,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MATERIAL: Material = Material {
+        color: Color { r: 0., g: 0., b: 0. },
+        specular: -1,
+        reflective: 0.,
+        transparency: None,
+    };
+
+    // Surface crossed by the line through the ray at fixed values of `t`,
+    // relying on the default `hit_intervals`.
+    struct Crossings(Vec<f64>);
+
+    impl SceneObject for Crossings {
+        fn hit_test(&self, ray: &Ray, t_range: &RangeInclusive<f64>) -> Option<HitTestResult> {
+            self.0.iter().find(|t| t_range.contains(t)).map(|&t| HitTestResult {
+                t,
+                point: ray.origin + ray.direction * t,
+                normal: (0., 0., 1.).into(),
+                material: MATERIAL,
+            })
+        }
+    }
+
+    #[test]
+    fn default_hit_intervals_step_in_distance_along_the_ray() {
+        // 0.5 units apart along a ray whose direction is 1000 units long
+        let ray = Ray { origin: Point::origin(), direction: (0., 0., 1000.).into() };
+        let intervals = Crossings(vec![1., 1.0005]).hit_intervals(&ray);
+        assert_eq!(intervals.len(), 1);
+        assert_eq!(intervals[0].exit.map(|hit| hit.t), Some(1.0005));
+    }
+
+    #[test]
+    fn default_hit_intervals_drop_an_unpaired_hit() {
+        let ray = Ray { origin: Point::origin(), direction: (0., 0., 1.).into() };
+        let intervals = Crossings(vec![1., 2., 3.]).hit_intervals(&ray);
+        assert_eq!(intervals.len(), 1);
+        assert!(intervals[0].exit.is_some());
+
+        let csg = CsgObject {
+            object_a: Box::new(Crossings(vec![1., 2., 3.])),
+            operation: BooleanOperation::UNION,
+            object_b: Box::new(Crossings(vec![])),
+        };
+        assert!(csg.hit_test(&ray, &(2.5..=f64::INFINITY)).is_none());
+    }
+
+    const SOLID: Material = Material {
+        color: Color::red(),
+        specular: -1,
+        reflective: 0.,
+        transparency: None,
+    };
+
+    fn vector(x: f64, y: f64, z: f64) -> Vector {
+        Point::from((x, y, z)) - Point::from((0., 0., 0.))
+    }
+
+    fn z_ray() -> Ray {
+        Ray { origin: (0., 0., 0.).into(), direction: vector(0., 0., 1.) }
+    }
+
+    // Sphere the ray along +z enters at `t0` and leaves at `t1`.
+    fn span(t0: f64, t1: f64) -> Box<dyn SceneObject + Sync> {
+        Box::new(SphereObject {
+            sphere: Sphere { center: (0., 0., (t0 + t1) / 2.).into(), radius: (t1 - t0) / 2. },
+            material: SOLID,
+        })
+    }
+
+    fn csg(a: Box<dyn SceneObject + Sync>, operation: BooleanOperation, b: Box<dyn SceneObject + Sync>) -> Vec<HitInterval> {
+        CsgObject { object_a: a, operation, object_b: b }.hit_intervals(&z_ray())
+    }
+
+    fn spans(intervals: &[HitInterval]) -> Vec<(f64, f64)> {
+        intervals.iter().map(|i| (i.enter_t(), i.exit_t())).collect()
+    }
+
+    fn assert_spans(intervals: &[HitInterval], expected: &[(f64, f64)]) {
+        let actual = spans(intervals);
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a.0 - e.0).abs() < 1e-9 && (a.1 - e.1).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    // Normals point out of the result: against the ray on entry, along it on exit.
+    fn assert_normals_face_out(intervals: &[HitInterval]) {
+        for interval in intervals {
+            assert!(interval.enter.unwrap().normal.z() < 0.);
+            assert!(interval.exit.unwrap().normal.z() > 0.);
+        }
+    }
+
+    #[test]
+    fn csg_union() {
+        assert_spans(&csg(span(1., 3.), BooleanOperation::UNION, span(5., 7.)), &[(1., 3.), (5., 7.)]);
+        assert_spans(&csg(span(1., 7.), BooleanOperation::UNION, span(3., 5.)), &[(1., 7.)]);
+        assert_spans(&csg(span(1., 4.), BooleanOperation::UNION, span(3., 6.)), &[(1., 6.)]);
+        // exits sort before entries at the same distance, so touching spans stay apart
+        assert_spans(&csg(span(1., 3.), BooleanOperation::UNION, span(3., 5.)), &[(1., 3.), (3., 5.)]);
+        assert_normals_face_out(&csg(span(1., 4.), BooleanOperation::UNION, span(3., 6.)));
+    }
+
+    #[test]
+    fn csg_intersection() {
+        assert_spans(&csg(span(1., 3.), BooleanOperation::INTERSECTION, span(5., 7.)), &[]);
+        assert_spans(&csg(span(1., 7.), BooleanOperation::INTERSECTION, span(3., 5.)), &[(3., 5.)]);
+        assert_spans(&csg(span(1., 4.), BooleanOperation::INTERSECTION, span(3., 6.)), &[(3., 4.)]);
+        assert_spans(&csg(span(1., 3.), BooleanOperation::INTERSECTION, span(3., 5.)), &[]);
+        assert_normals_face_out(&csg(span(1., 4.), BooleanOperation::INTERSECTION, span(3., 6.)));
+    }
+
+    #[test]
+    fn csg_subtraction() {
+        assert_spans(&csg(span(1., 3.), BooleanOperation::SUBTRACTION, span(5., 7.)), &[(1., 3.)]);
+        assert_spans(&csg(span(3., 5.), BooleanOperation::SUBTRACTION, span(1., 7.)), &[]);
+        assert_spans(&csg(span(1., 3.), BooleanOperation::SUBTRACTION, span(3., 5.)), &[(1., 3.)]);
+
+        // the subtracted sphere's surfaces become the result's, facing the other way
+        let hollow = csg(span(1., 7.), BooleanOperation::SUBTRACTION, span(3., 5.));
+        assert_spans(&hollow, &[(1., 3.), (5., 7.)]);
+        assert_normals_face_out(&hollow);
+        let bitten = csg(span(2., 6.), BooleanOperation::SUBTRACTION, span(1., 4.));
+        assert_spans(&bitten, &[(4., 6.)]);
+        assert_normals_face_out(&bitten);
+    }
+
+    #[test]
+    fn csg_nests() {
+        let ring = Box::new(CsgObject {
+            object_a: span(1., 9.),
+            operation: BooleanOperation::SUBTRACTION,
+            object_b: span(3., 7.),
+        });
+        let intervals = csg(ring, BooleanOperation::UNION, span(4., 5.));
+        assert_spans(&intervals, &[(1., 3.), (4., 5.), (7., 9.)]);
+        assert_normals_face_out(&intervals);
+    }
+
+    fn torus() -> TorusObject {
+        TorusObject {
+            center: (0., 0., 0.).into(),
+            axis: vector(0., 1., 0.),
+            major_radius: 2.,
+            minor_radius: 0.5,
+            material: SOLID,
+        }
+    }
+
+    fn torus_hits(origin: (f64, f64, f64), direction: Vector) -> Vec<f64> {
+        let ray = Ray { origin: origin.into(), direction };
+        torus().hit_intervals(&ray).iter().flat_map(|i| [i.enter_t(), i.exit_t()]).collect()
+    }
+
+    #[test]
+    fn torus_hit_crosses_the_tube_twice() {
+        let hits = torus_hits((-5., 0., 0.), vector(1., 0., 0.));
+        let expected = [2.5, 3.5, 6.5, 7.5];
+        assert_eq!(hits.len(), 4, "{:?}", hits);
+        for (t, e) in hits.iter().zip(expected) {
+            assert!((t - e).abs() < 1e-9, "{:?}", hits);
+        }
+        let ray = Ray { origin: (-5., 0., 0.).into(), direction: vector(1., 0., 0.) };
+        let hit = torus().hit_test(&ray, &(0.0..=f64::INFINITY)).unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-9);
+        assert!((hit.normal.x() + 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn torus_hit_from_far_away_stays_accurate() {
+        let hits = torus_hits((-1e6, 0., 0.), vector(2., 0., 0.));
+        assert_eq!(hits.len(), 4);
+        assert!((hits[0] - (1e6 - 2.5) / 2.).abs() < 1e-6, "{:?}", hits);
+    }
+
+    #[test]
+    fn torus_misses() {
+        assert!(torus_hits((-5., 3., 0.), vector(1., 0., 0.)).is_empty());
+        assert!(torus_hits((-5., 0., 0.), vector(-1., 0., 0.)).iter().all(|&t| t < 0.));
+        let ray = Ray { origin: (-5., 0., 0.).into(), direction: vector(-1., 0., 0.) };
+        assert!(torus().hit_test(&ray, &(0.0..=f64::INFINITY)).is_none());
+    }
+
+    #[test]
+    fn torus_hole_lets_rays_through() {
+        assert!(torus_hits((0., 5., 0.), vector(0., -1., 0.)).is_empty());
+        assert!(torus_hits((0., 5., 0.3), vector(0., -1., 0.)).is_empty());
+    }
+
+    #[test]
+    fn torus_grazing_rays() {
+        // just below the top of the tube: two short spans around x = -2 and 2
+        let hits = torus_hits((-5., 0.499, 0.), vector(1., 0., 0.));
+        assert_eq!(hits.len(), 4, "{:?}", hits);
+        for t in hits {
+            let (x, y) = (t - 5., 0.499);
+            let off_surface = (x.abs() - 2.).powi(2) + y * y - 0.25;
+            assert!(off_surface.abs() < 1e-9, "t = {}", t);
+        }
+        // just above it
+        assert!(torus_hits((-5., 0.501, 0.), vector(1., 0., 0.)).is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::components::*;
+
+/// Squeezes the colors of a render into the displayable 0-255 range.
+#[derive(Debug, Clone, Copy)]
+pub enum ToneMapping {
+    /// Cuts off everything above 255.
+    CLAMP,
+    /// `x / (1 + x)`, which brings any brightness below white but flattens
+    /// highlights.
+    REINHARD,
+    /// Filmic curve fitted to the ACES reference transform, with a gentle toe
+    /// and shoulder.
+    ACES,
+}
+
+/// How `Canvas::data` turns the rendered linear colors into 8-bit output.
+/// The default leaves colors as they are apart from clamping.
+#[derive(Debug, Clone, Copy)]
+pub struct OutputTransform {
+    /// Brightness change in stops, each one doubling it.
+    pub exposure: f64,
+    pub tone_mapping: ToneMapping,
+    /// Whether to encode the output as sRGB, as PNG viewers expect.
+    pub srgb: bool,
+}
+
+impl Default for OutputTransform {
+    fn default() -> Self {
+        OutputTransform {
+            exposure: 0.,
+            tone_mapping: ToneMapping::CLAMP,
+            srgb: false,
+        }
+    }
+}
+
+impl OutputTransform {
+    pub fn apply(&self, color: Color) -> ColorPack {
+        let color = color * 2f64.powf(self.exposure);
+        let color = match self.tone_mapping {
+            ToneMapping::CLAMP => color,
+            ToneMapping::REINHARD => color.map(|c| {
+                let c = c.max(0.) / 255.;
+                c / (1. + c) * 255.
+            }),
+            ToneMapping::ACES => color.map(|c| {
+                let c = c.max(0.) / 255.;
+                (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14) * 255.
+            }),
+        };
+        if self.srgb {
+            color.map(|c| c.min(255.)).linear_to_srgb().clamp()
+        } else {
+            color.clamp()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(value: f32) -> Color {
+        Color { r: value, g: value, b: value }
+    }
+
+    fn output(tone_mapping: ToneMapping, exposure: f64, srgb: bool) -> OutputTransform {
+        OutputTransform { exposure, tone_mapping, srgb }
+    }
+
+    #[test]
+    fn default_only_clamps() {
+        let default = OutputTransform::default();
+        assert_eq!(default.apply(gray(0.)), (0, 0, 0));
+        assert_eq!(default.apply(gray(100.4)), (100, 100, 100));
+        assert_eq!(default.apply(gray(255.)), (255, 255, 255));
+        assert_eq!(default.apply(gray(1e6)), (255, 255, 255));
+        assert_eq!(default.apply(gray(-5.)), (0, 0, 0));
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        assert_eq!(output(ToneMapping::CLAMP, 1., false).apply(gray(100.)).0, 200);
+        assert_eq!(output(ToneMapping::CLAMP, -2., false).apply(gray(100.)).0, 25);
+        assert_eq!(output(ToneMapping::CLAMP, 0., false).apply(gray(100.)).0, 100);
+    }
+
+    #[test]
+    fn reinhard() {
+        let reinhard = output(ToneMapping::REINHARD, 0., false);
+        assert_eq!(reinhard.apply(gray(0.)).0, 0);
+        // 1 in linear units is half way to white
+        assert_eq!(reinhard.apply(gray(255.)).0, 128);
+        assert_eq!(reinhard.apply(gray(255. * 3.)).0, 191);
+        assert_eq!(reinhard.apply(gray(1e9)).0, 255);
+    }
+
+    #[test]
+    fn aces() {
+        let aces = output(ToneMapping::ACES, 0., false);
+        assert_eq!(aces.apply(gray(0.)).0, 0);
+        // 2.54 / 3.16 of white
+        assert_eq!(aces.apply(gray(255.)).0, 205);
+        assert_eq!(aces.apply(gray(1e9)).0, 255);
+    }
+
+    #[test]
+    fn srgb_encoding() {
+        let srgb = output(ToneMapping::CLAMP, 0., true);
+        assert_eq!(srgb.apply(gray(0.)).0, 0);
+        assert_eq!(srgb.apply(gray(255.)).0, 255);
+        assert_eq!(srgb.apply(gray(1e6)).0, 255);
+        // middle gray
+        assert_eq!(srgb.apply(gray(0.18 * 255.)).0, 118);
+
+        // the linear segment meets the curve at 0.0031308, which encodes to 0.04045
+        let threshold = gray(0.0031308 * 255.).linear_to_srgb().r / 255.;
+        assert!((threshold - 0.04045).abs() < 1e-5, "{}", threshold);
+        let above = gray(0.0032 * 255.).linear_to_srgb().r / 255.;
+        assert!((above - (1.055 * 0.0032f32.powf(1. / 2.4) - 0.055)).abs() < 1e-5);
+        let round_trip = gray(0.5 * 255.).linear_to_srgb().srgb_to_linear().r;
+        assert!((round_trip - 0.5 * 255.).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::components::Color;
+
+/// Small, fast pseudo-random generator (SplitMix64). The same seed always
+/// gives the same sequence.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Generator of its own for pass `pass` over pixel `(x, y)`, so that a
+    /// pixel's samples don't depend on the order pixels are rendered in.
+    pub fn for_pixel(seed: u64, x: u32, y: u32, pass: u32) -> Self {
+        Self::new(mix(mix(seed ^ mix((y as u64) << 32 | x as u64)) ^ pass as u64))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        mix(self.state)
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [0, n).
+    pub fn below(&mut self, n: u32) -> u32 {
+        (self.next_f64() * n as f64) as u32
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i as u32 + 1) as usize);
+        }
+    }
+}
+
+/// One sample of a pixel. Both points lie in the unit square: `pixel` is
+/// the position within the pixel, from its top-left corner, and `lens` the
+/// point of the camera's lens the ray leaves from.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub pixel: (f64, f64),
+    pub lens: (f64, f64),
+}
+
+/// Chooses where the samples of a pixel are taken.
+pub trait Sampler {
+    /// `n * n` samples for pixel `(x, y)`. Passes after the first one give
+    /// more samples for the same pixel, spread among the earlier ones.
+    fn pixel_samples(&self, x: u32, y: u32, n: u32, pass: u32) -> Vec<Sample>;
+}
+
+/// Samples at the corners of an n x n grid. Cheap, but prone to aliasing.
+/// Later passes shift the grid within its cells.
+#[derive(Debug, Clone, Copy)]
+pub struct RegularSampler;
+
+impl Sampler for RegularSampler {
+    fn pixel_samples(&self, _x: u32, _y: u32, n: u32, pass: u32) -> Vec<Sample> {
+        let shift = (radical_inverse(2, pass), radical_inverse(3, pass));
+        let lens_shift = ((0.5 + radical_inverse(5, pass)).fract(), (0.5 + radical_inverse(7, pass)).fract());
+        let mut samples = Vec::with_capacity((n * n) as usize);
+        for i in 0..n {
+            for j in 0..n {
+                // Lens samples cover the same n x n grid as the pixel ones, shuffled
+                // so that neighbouring pixel samples don't see the lens from nearby.
+                samples.push(Sample {
+                    pixel: ((i as f64 + shift.0) / n as f64, (j as f64 + shift.1) / n as f64),
+                    lens: (
+                        (((i + j) % n) as f64 + lens_shift.0) / n as f64,
+                        (((i + 2 * j) % n) as f64 + lens_shift.1) / n as f64,
+                    ),
+                });
+            }
+        }
+        samples
+    }
+}
+
+/// One sample at a random point of each cell of an n x n grid, for both the
+/// pixel and the lens, with the lens cells randomly paired with pixel ones.
+#[derive(Debug, Clone, Copy)]
+pub struct JitteredSampler {
+    pub seed: u64,
+}
+
+impl Sampler for JitteredSampler {
+    fn pixel_samples(&self, x: u32, y: u32, n: u32, pass: u32) -> Vec<Sample> {
+        let mut rng = Rng::for_pixel(self.seed, x, y, pass);
+        let mut lens_cells: Vec<u32> = (0..n * n).collect();
+        rng.shuffle(&mut lens_cells);
+        let mut jittered = |cell: u32| (
+            ((cell % n) as f64 + rng.next_f64()) / n as f64,
+            ((cell / n) as f64 + rng.next_f64()) / n as f64,
+        );
+        (0..n * n)
+            .zip(lens_cells)
+            .map(|(pixel, lens)| Sample {
+                pixel: jittered(pixel),
+                lens: jittered(lens),
+            })
+            .collect()
+    }
+}
+
+/// `i` written in `base` and mirrored around the radix point.
+fn radical_inverse(base: u32, mut i: u32) -> f64 {
+    let mut result = 0.;
+    let mut digit = 1. / base as f64;
+    while i > 0 {
+        result += (i % base) as f64 * digit;
+        i /= base;
+        digit /= base as f64;
+    }
+    result
+}
+
+/// Low-discrepancy Halton sequence, in bases 2 and 3 for the pixel and 5 and
+/// 7 for the lens. Each pixel shifts the sequence by a random offset so that
+/// neighbours don't share the same pattern, and each pass carries on where
+/// the previous one stopped.
+#[derive(Debug, Clone, Copy)]
+pub struct HaltonSampler {
+    pub seed: u64,
+}
+
+impl Sampler for HaltonSampler {
+    fn pixel_samples(&self, x: u32, y: u32, n: u32, pass: u32) -> Vec<Sample> {
+        let mut rng = Rng::for_pixel(self.seed, x, y, 0);
+        let offsets = [rng.next_f64(), rng.next_f64(), rng.next_f64(), rng.next_f64()];
+        let point = |base: u32, offset: f64, i: u32| (radical_inverse(base, i) + offset).fract();
+        (pass * n * n..(pass + 1) * n * n)
+            .map(|i| Sample {
+                pixel: (point(2, offsets[0], i), point(3, offsets[1], i)),
+                lens: (point(5, offsets[2], i), point(7, offsets[3], i)),
+            })
+            .collect()
+    }
+}
+
+/// Adaptive supersampling. Every pixel gets a first pass of n x n samples,
+/// then more passes for as long as its samples differ by more than
+/// `threshold` in contrast, until it has `max_samples`.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveSampling {
+    pub threshold: f64,
+    pub max_samples: u32,
+}
+
+impl AdaptiveSampling {
+    /// Largest contrast between `colors` over the three channels, as
+    /// `(max - min) / (max + min)`, which is 0 for equal colors and 1 when
+    /// one of them is black.
+    pub fn contrast(colors: &[Color]) -> f64 {
+        let channel = |value: fn(&Color) -> f32| {
+            let (min, max) = colors.iter().map(value).fold((f32::INFINITY, 0f32), |(min, max), v| (min.min(v), max.max(v)));
+            if max > 0. {
+                ((max - min) / (max + min)) as f64
+            } else {
+                0.
+            }
+        };
+        channel(|c| c.r).max(channel(|c| c.g)).max(channel(|c| c.b))
+    }
+
+    /// Whether a pixel whose samples so far are `colors` needs another pass.
+    pub fn refine(&self, colors: &[Color]) -> bool {
+        colors.len() < self.max_samples as usize && Self::contrast(colors) > self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_unit_square(p: (f64, f64)) -> bool {
+        (0. ..1.).contains(&p.0) && (0. ..1.).contains(&p.1)
+    }
+
+    #[test]
+    fn rng_matches_splitmix64() {
+        let mut rng = Rng::new(0);
+        assert_eq!(rng.next_u64(), 0xe220a8397b1dcdaf);
+        assert_eq!(rng.next_u64(), 0x6e789e6aa1b965f4);
+        for _ in 0..1000 {
+            assert!((0. ..1.).contains(&rng.next_f64()));
+        }
+    }
+
+    #[test]
+    fn radical_inverse_mirrors_digits() {
+        assert_eq!([0, 1, 2, 3, 4].map(|i| radical_inverse(2, i)), [0., 0.5, 0.25, 0.75, 0.125]);
+        let base_3 = [0, 1, 2, 3, 4].map(|i| radical_inverse(3, i));
+        for (a, b) in base_3.iter().zip([0., 1. / 3., 2. / 3., 1. / 9., 4. / 9.]) {
+            assert!((a - b).abs() < 1e-15);
+        }
+    }
+
+    fn samplers() -> Vec<Box<dyn Sampler>> {
+        vec![Box::new(RegularSampler), Box::new(JitteredSampler { seed: 3 }), Box::new(HaltonSampler { seed: 3 })]
+    }
+
+    fn coordinates(samples: &[Sample]) -> Vec<[f64; 4]> {
+        samples.iter().map(|s| [s.pixel.0, s.pixel.1, s.lens.0, s.lens.1]).collect()
+    }
+
+    #[test]
+    fn samples_lie_in_the_unit_square() {
+        for sampler in samplers() {
+            for n in 1..=4 {
+                let samples = sampler.pixel_samples(5, 9, n, 0);
+                assert_eq!(samples.len(), (n * n) as usize);
+                assert!(samples.iter().all(|s| in_unit_square(s.pixel) && in_unit_square(s.lens)));
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_samplers_are_deterministic() {
+        let jittered = JitteredSampler { seed: 11 };
+        let halton = HaltonSampler { seed: 11 };
+        assert_eq!(coordinates(&jittered.pixel_samples(4, 2, 3, 0)), coordinates(&jittered.pixel_samples(4, 2, 3, 0)));
+        assert_eq!(coordinates(&halton.pixel_samples(4, 2, 3, 0)), coordinates(&halton.pixel_samples(4, 2, 3, 0)));
+        assert_ne!(coordinates(&jittered.pixel_samples(4, 2, 3, 0)), coordinates(&jittered.pixel_samples(5, 2, 3, 0)));
+        assert_ne!(
+            coordinates(&jittered.pixel_samples(4, 2, 3, 0)),
+            coordinates(&JitteredSampler { seed: 12 }.pixel_samples(4, 2, 3, 0)),
+        );
+    }
+
+    #[test]
+    fn halton_sampler_uses_bases_2_3_5_and_7() {
+        let samples = HaltonSampler { seed: 5 }.pixel_samples(1, 1, 4, 0);
+        for (axis, &base) in [2, 3, 5, 7].iter().enumerate() {
+            // each pixel shifts the whole sequence by the same offset
+            let offsets: Vec<f64> = coordinates(&samples).iter().enumerate()
+                .map(|(i, c)| (c[axis] - radical_inverse(base, i as u32)).rem_euclid(1.))
+                .collect();
+            assert!(offsets.iter().all(|o| (o - offsets[0]).abs() < 1e-12 || (o - offsets[0]).abs() > 1. - 1e-12), "base {}", base);
+        }
+    }
+
+}